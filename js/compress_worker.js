@@ -0,0 +1,23 @@
+// Worker script for `Handles.set_worker_pool`, only works with a build including the `worker-pool` feature.
+//
+// Adjust the import to where the generated bindings are served, then create workers as module workers:
+//   handles.set_worker_pool(() => new Worker(new URL('./compress_worker.js', import.meta.url), { type: 'module' }), 4);
+
+import init, { compress_in_worker } from './wasm_zip_stream.js';
+
+const ready = init();
+
+self.onmessage = async (event) => {
+  const { id, slice, level, dictionaryLength, last } = event.data;
+  try {
+    await ready;
+    const compressed = await compress_in_worker(slice, level, dictionaryLength, last);
+    const data = compressed.data;
+    self.postMessage({ id, data, crc32: compressed.crc32, uncompressedSize: compressed.uncompressed_size }, [data.buffer]);
+    compressed.free();
+  } catch (error) {
+    // a `WasmError` loses its getters when cloned, copy its fields
+    const { code, arg0, arg1, arg2, arg3 } = error;
+    self.postMessage({ id, error: code === undefined ? String(error) : { code, arg0, arg1, arg2, arg3 } });
+  }
+};
@@ -0,0 +1,195 @@
+// Main thread side of `js/pipeline_worker.js`, every method of `HandlesProxy` returns a Promise.
+//
+//   const proxy = new HandlesProxy(
+//     new Worker(new URL('./pipeline_worker.js', import.meta.url), { type: 'module' }),
+//     (fileName) => streamSaver.createWriteStream(fileName),
+//   );
+//   await proxy.scan(entries);
+//   const summary = await proxy.compress('archive', 6, controller.signal);
+
+// methods forwarded as they are, their arguments and results can be cloned,
+// `set_layout` and `set_worker_pool` take plain values which the worker turns into what `Handles` expects
+const FORWARDED_METHODS = [
+  'compress', 'compress_with_options', 'compress_transform', 'transform', 'recover',
+  'scan_files', 'plan', 'plan_totals', 'remove_entry', 'rename_entry', 'move_entry',
+  'release_buffers', 'state', 'pause', 'resume', 'estimate', 'set_layout', 'set_worker_pool',
+];
+
+// methods of `ArchiveJob`, forwarded to the job living in the worker
+const JOB_METHODS = ['compress', 'compress_with_options', 'status', 'summary', 'pause', 'resume', 'free'];
+
+// callbacks relayed from the worker
+const RELAYED_CALLBACKS = [
+  'compress_progress', 'byte_progress', 'eta', 'average_speed', 'current_speed', 'current_file', 'state_change',
+];
+
+// callbacks invoked on the main thread, where `scan` runs
+const SCAN_CALLBACKS = ['scan_progress', 'scan_discovery', 'scan_filter'];
+
+export class HandlesProxy {
+  /**
+   * @param {Worker} worker - a module worker running `js/pipeline_worker.js`
+   * @param {(fileName: string) => WritableStream} createWriteStream - where each output goes
+   */
+  constructor(worker, createWriteStream) {
+    this.worker = worker;
+    this.createWriteStream = createWriteStream;
+    this.nextId = 0;
+    this.calls = new Map();
+    this.callbacks = new Map();
+    this.scanCallbacks = new Map();
+
+    worker.onmessage = ({ data }) => {
+      switch (data.type) {
+        case 'writer':
+          // errors reach the caller through the rejected call, the pipe only forwards them
+          data.readable.pipeTo(this.createWriteStream(data.name)).catch(() => {});
+          return;
+        case 'callback':
+          this.callbacks.get(data.name)?.(...data.args);
+          return;
+        case 'result':
+        case 'error': {
+          const call = this.calls.get(data.id);
+          this.calls.delete(data.id);
+          call.cleanup();
+          if (data.type === 'result') {
+            call.resolve(data.value);
+          } else {
+            call.reject(data.error);
+          }
+          return;
+        }
+      }
+    };
+
+    for (const method of FORWARDED_METHODS) {
+      this[method] = (...args) => this.call(method, args);
+    }
+    for (const name of RELAYED_CALLBACKS) {
+      this[`register_${name}`] = (callback) => {
+        this.callbacks.set(name, callback);
+        worker.postMessage({ type: 'register', name });
+      };
+    }
+    for (const name of SCAN_CALLBACKS) {
+      this[`register_${name}`] = (callback) => {
+        this.scanCallbacks.set(name, callback);
+      };
+    }
+  }
+
+  /**
+   * Snapshot the plan and the registered callbacks into a job living in the worker.
+   *
+   * @returns {Promise<ArchiveJobProxy>} call `free` once the job is no longer needed
+   */
+  async create_job() {
+    const { jobId } = await this.call('create_job', []);
+    return new ArchiveJobProxy(this, jobId);
+  }
+
+  /**
+   * Walk `FileSystemEntry`s on the main thread, `FileSystemEntry`s can not be sent to a worker but `File`s can.
+   *
+   * The "scan_progress", "scan_discovery" and "scan_filter" callbacks are invoked like `Handles.scan` does.
+   *
+   * @param {FileSystemEntry[]} entries - e.g. from `DataTransferItem.webkitGetAsEntry`
+   * @param {AbortSignal} [signal] - once aborted the walk stops and rejects with the signal's reason
+   * @returns {Promise<number>} number of entries in the plan
+   */
+  async scan(entries, signal) {
+    const files = [];
+    let directoriesFound = 0;
+    let filesFound = 0;
+    const filter = this.scanCallbacks.get('scan_filter');
+
+    // `archiveDir` is the archive path of the parent, children of a remapped directory follow it
+    const walk = async (entry, archiveDir) => {
+      signal?.throwIfAborted();
+      let path = archiveDir === undefined ? entry.fullPath.replace(/^\/+/, '') : `${archiveDir}/${entry.name}`;
+      const file = entry.isFile ? await new Promise((resolve, reject) => entry.file(resolve, reject)) : undefined;
+      if (filter) {
+        const decision = filter({ path: entry.fullPath, isDir: !entry.isFile, size: file?.size, lastModified: file?.lastModified });
+        if (decision === 'skip') {
+          return;
+        }
+        if (decision !== null && typeof decision === 'object' && typeof decision.archivePath === 'string') {
+          path = decision.archivePath;
+        } else if (decision !== undefined && decision !== null && decision !== 'keep') {
+          throw new TypeError(`invalid scan_filter result for ${entry.fullPath}: ${decision}`);
+        }
+      }
+
+      if (entry.isFile) {
+        filesFound += 1;
+      } else {
+        directoriesFound += 1;
+      }
+      this.scanCallbacks.get('scan_progress')?.(directoriesFound + filesFound);
+      this.scanCallbacks.get('scan_discovery')?.(directoriesFound, filesFound);
+
+      if (entry.isFile) {
+        files.push({ path, file });
+        return;
+      }
+      files.push({ path });
+      const reader = entry.createReader();
+      for (;;) {
+        const children = await new Promise((resolve, reject) => reader.readEntries(resolve, reject));
+        if (children.length === 0) {
+          return;
+        }
+        for (const child of children) {
+          await walk(child, path);
+        }
+      }
+    };
+    for (const entry of entries) {
+      await walk(entry, undefined);
+    }
+    return this.call('scan_files', [files]);
+  }
+
+  call(method, args, jobId) {
+    const id = this.nextId++;
+    const listeners = [];
+
+    // an `AbortSignal` can not be cloned, it is replaced by `{ abortSignal: id }` and its abort is forwarded
+    const replaceSignals = (value) => {
+      if (value instanceof AbortSignal) {
+        const signalId = `${id}-${listeners.length}`;
+        const forward = () => this.worker.postMessage({ type: 'abort', signalId, reason: String(value.reason) });
+        if (value.aborted) {
+          queueMicrotask(forward);
+        } else {
+          value.addEventListener('abort', forward, { once: true });
+        }
+        listeners.push(() => value.removeEventListener('abort', forward));
+        return { abortSignal: signalId };
+      }
+      if (Array.isArray(value)) {
+        return value.map(replaceSignals);
+      }
+      if (value !== null && typeof value === 'object' && Object.getPrototypeOf(value) === Object.prototype) {
+        return Object.fromEntries(Object.entries(value).map(([key, item]) => [key, replaceSignals(item)]));
+      }
+      return value;
+    };
+
+    return new Promise((resolve, reject) => {
+      const cleanup = () => listeners.forEach((remove) => remove());
+      this.calls.set(id, { resolve, reject, cleanup });
+      this.worker.postMessage({ type: 'call', id, method, args: replaceSignals(args), jobId });
+    });
+  }
+}
+
+/** An `ArchiveJob` living in the worker, created by `HandlesProxy.create_job`, every method returns a Promise. */
+export class ArchiveJobProxy {
+  constructor(proxy, jobId) {
+    for (const method of JOB_METHODS) {
+      this[method] = (...args) => proxy.call(method, args, jobId);
+    }
+  }
+}
@@ -0,0 +1,130 @@
+// Worker entry point hosting `Handles`, driven by `HandlesProxy` from `js/handles_proxy.js`.
+//
+// Adjust the import to where the generated bindings are served, the worker must be a module worker.
+//
+// Messages from the page:
+//   { type: 'call', id, method, args, jobId? }
+//                                        invoke `handles[method](...args)`, or the method of the job created by call `jobId`,
+//                                        answered by { type: 'result', id, value } or { type: 'error', id, error }
+//   { type: 'abort', signalId, reason }  abort the `AbortSignal` standing for `{ abortSignal: signalId }` in `args`
+//   { type: 'register', name }           register a callback relayed as { type: 'callback', name, args }
+// Messages to the page, besides answers and callbacks:
+//   { type: 'writer', name, readable }   a writer was created, `readable` carries its output and must be piped to a WritableStream
+
+import init, { initialize_context, LayoutOptions } from './wasm_zip_stream.js';
+
+const controllers = new Map();
+// jobs made by `create_job`, by the id of that call, until their `free`
+const jobs = new Map();
+
+// methods whose arguments can not be cloned, the page sends plain values instead
+const ADAPTERS = {
+  set_layout(handles, { rootFolder, stripComponents, flatten, directoryEntries, keepEmptyDirectories } = {}) {
+    const layout = new LayoutOptions();
+    try {
+      if (rootFolder !== undefined) layout.set_root_folder(rootFolder);
+    } catch (error) {
+      layout.free();
+      throw error;
+    }
+    if (stripComponents !== undefined) layout.set_strip_components(stripComponents);
+    if (flatten !== undefined) layout.set_flatten(flatten);
+    if (directoryEntries !== undefined) layout.set_directory_entries(directoryEntries);
+    if (keepEmptyDirectories !== undefined) layout.set_keep_empty_directories(keepEmptyDirectories);
+    handles.set_layout(layout);
+  },
+  // functions can not be sent, the workers of the pool are created from a module script URL
+  set_worker_pool(handles, workerUrl, size) {
+    return handles.set_worker_pool(() => new Worker(workerUrl, { type: 'module' }), size);
+  },
+};
+
+const ready = init().then(() => initialize_context((name) => {
+  // `create_writer` is called synchronously, the page gets the readable side and pipes it wherever it wants
+  // chunks are copied, the buffer of a chunk is reused once its write settles
+  const { readable, writable } = new TransformStream({ transform(chunk, controller) { controller.enqueue(chunk.slice()); } });
+  self.postMessage({ type: 'writer', name, readable }, [readable]);
+  return writable.getWriter();
+}));
+
+// wasm objects can not be cloned, their getters are copied instead
+function toPlain(value) {
+  if (Array.isArray(value)) {
+    return value.map(toPlain);
+  }
+  if (value !== null && typeof value === 'object' && '__wbg_ptr' in value) {
+    const plain = {};
+    for (const [key, descriptor] of Object.entries(Object.getOwnPropertyDescriptors(Object.getPrototypeOf(value)))) {
+      if (descriptor.get) {
+        plain[key] = toPlain(value[key]);
+      }
+    }
+    // `JobSummary.entries` is a method
+    if (typeof value.entries === 'function') {
+      plain.entries = toPlain(value.entries());
+    }
+    value.free();
+    return plain;
+  }
+  return value;
+}
+
+// `signalIds` collects the signals created for one call, they are forgotten once it settles
+function fromPlain(value, signalIds) {
+  if (Array.isArray(value)) {
+    return value.map((item) => fromPlain(item, signalIds));
+  }
+  if (value !== null && typeof value === 'object' && Object.getPrototypeOf(value) === Object.prototype) {
+    if ('abortSignal' in value && Object.keys(value).length === 1) {
+      const controller = new AbortController();
+      controllers.set(value.abortSignal, controller);
+      signalIds.push(value.abortSignal);
+      return controller.signal;
+    }
+    return Object.fromEntries(Object.entries(value).map(([key, item]) => [key, fromPlain(item, signalIds)]));
+  }
+  return value;
+}
+
+self.onmessage = async ({ data }) => {
+  const handles = await ready;
+  switch (data.type) {
+    case 'abort':
+      controllers.get(data.signalId)?.abort(data.reason);
+      controllers.delete(data.signalId);
+      return;
+    case 'register':
+      handles[`register_${data.name}`]((...args) => {
+        self.postMessage({ type: 'callback', name: data.name, args: toPlain(args) });
+      });
+      return;
+    case 'call': {
+      const signalIds = [];
+      try {
+        const args = fromPlain(data.args, signalIds);
+        let value;
+        if (data.jobId !== undefined) {
+          value = await jobs.get(data.jobId)[data.method](...args);
+          if (data.method === 'free') {
+            jobs.delete(data.jobId);
+          }
+        } else if (data.method in ADAPTERS) {
+          value = await ADAPTERS[data.method](handles, ...args);
+        } else {
+          value = await handles[data.method](...args);
+        }
+        // a job stays in the worker, the page gets its id
+        if (data.method === 'create_job' && data.jobId === undefined) {
+          jobs.set(data.id, value);
+          value = { jobId: data.id };
+        }
+        self.postMessage({ type: 'result', id: data.id, value: toPlain(value) });
+      } catch (error) {
+        self.postMessage({ type: 'error', id: data.id, error: error !== null && typeof error === 'object' ? toPlain(error) : String(error) });
+      } finally {
+        signalIds.forEach((signalId) => controllers.delete(signalId));
+      }
+      return;
+    }
+  }
+};
@@ -16,6 +16,7 @@ pub struct FileHeader {
   crc_32                : u32,
   compressed_size_u64   : u64,
   uncompressed_size_u64 : u64,
+  compression_method    : u16,
 }
 
 impl FileHeader {
@@ -38,6 +39,7 @@ impl FileHeader {
       crc_32: 0_u32,
       compressed_size_u64: 0_u64,
       uncompressed_size_u64: 0_u64,
+      compression_method: if is_dir { COMPRESSION_METHOD_DIR } else { COMPRESSION_METHOD },
     }
   }
 
@@ -71,7 +73,7 @@ impl FileHeader {
       buffer.extend_from_slice(&0x04034b50_u32.to_le_bytes()); // signature
       buffer.extend_from_slice(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes());
       buffer.extend_from_slice(&GENERAL_PURPOSE_BIG_FLAG.to_le_bytes());
-      buffer.extend_from_slice(&COMPRESSION_METHOD.to_le_bytes());
+      buffer.extend_from_slice(&self.compression_method.to_le_bytes());
       buffer.extend_from_slice(&0_u16.to_le_bytes()); // last mod file time
       buffer.extend_from_slice(&0_u16.to_le_bytes()); // last mod file date
       buffer.extend_from_slice(&0_u32.to_le_bytes()); // crc32
@@ -127,7 +129,7 @@ impl FileHeader {
       buffer.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
       buffer.extend_from_slice(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes());
       buffer.extend_from_slice(&GENERAL_PURPOSE_BIG_FLAG.to_le_bytes());
-      buffer.extend_from_slice(&COMPRESSION_METHOD.to_le_bytes());
+      buffer.extend_from_slice(&self.compression_method.to_le_bytes());
       buffer.extend_from_slice(&0_u16.to_le_bytes()); // last mod file time
       buffer.extend_from_slice(&0_u16.to_le_bytes()); // last mod file date
       buffer.extend_from_slice(&self.crc_32.to_le_bytes());
@@ -156,4 +158,9 @@ impl FileHeader {
   pub fn set_uncompressed_size_u64(&mut self, size: u64) {
     self.uncompressed_size_u64 = size;
   }
+
+  /// Only for files, "deflate" by default.
+  pub fn set_compression_method(&mut self, compression_method: u16) {
+    self.compression_method = compression_method;
+  }
 }
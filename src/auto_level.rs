@@ -0,0 +1,211 @@
+//! The type `AutoLevel` picks the deflate level of each file for `level: "auto"`: the best ratio which keeps a target speed.
+//!
+//! The first few MB are compressed once more at levels 1, 6 and 9 to sample their speed and ratio, which gives the
+//! starting level. Afterwards the speed measured over whole files, reading and writing included, moves the level by one
+//! step between files.
+
+use std::io::Write;
+
+use flate2::{write::DeflateEncoder, Compression};
+
+/// unit: byte/s of input
+pub const DEFAULT_TARGET_SPEED: f64 = 20_f64 * 1024_f64 * 1024_f64;
+
+const SAMPLE_LEVELS: [u32; 3] = [1, 6, 9];
+/// bytes sampled at each of `SAMPLE_LEVELS`
+const SAMPLE_SIZE: usize = 4 * 1024 * 1024;
+/// so that the sample spreads over a few files
+pub const MAX_SAMPLE_PER_FILE: usize = 1024 * 1024;
+/// the speed is measured over at least this many bytes before the level moves
+const MIN_WINDOW: u64 = 8 * 1024 * 1024;
+/// deflate is only part of the work, a level is chosen or raised only if faster than the target by this factor
+const HEADROOM: f64 = 1.25;
+/// a higher level must save at least this share of the output to be worth its time
+const MIN_RATIO_GAIN: f64 = 0.01;
+
+#[derive(Default, Clone, Copy)]
+struct Sample {
+  input: u64,
+  output: u64,
+  /// unit: ms
+  time: f64,
+}
+
+impl Sample {
+  /// unit: byte/ms
+  fn speed(&self) -> f64 {
+    self.input as f64 / self.time.max(0.001_f64)
+  }
+
+  fn ratio(&self) -> f64 {
+    self.output as f64 / self.input.max(1) as f64
+  }
+}
+
+pub struct AutoLevel {
+  /// unit: byte/ms
+  target: f64,
+  level: u32,
+  /// one per `SAMPLE_LEVELS`
+  samples: [Sample; 3],
+  sampled: usize,
+  window_bytes: u64,
+  /// unit: ms
+  window_time: f64,
+}
+
+impl AutoLevel {
+  /// `target_speed` is in bytes of input per second.
+  pub fn new(target_speed: f64) -> Self {
+    Self {
+      target: target_speed / 1000_f64,
+      level: 6,
+      samples: [Sample::default(); 3],
+      sampled: 0,
+      window_bytes: 0,
+      window_time: 0_f64,
+    }
+  }
+
+  /// Level of the next file.
+  pub fn level(&self) -> u32 {
+    self.level
+  }
+
+  pub fn is_sampling(&self) -> bool {
+    self.sampled < SAMPLE_SIZE
+  }
+
+  /// Compress the start of a file at every sampled level, `now` is a clock in ms.
+  pub fn sample(&mut self, data: &[u8], now: impl Fn() -> f64) {
+    if !self.is_sampling() || data.is_empty() {
+      return;
+    }
+    let data = &data[..data.len().min(MAX_SAMPLE_PER_FILE).min(SAMPLE_SIZE - self.sampled)];
+    for (sample, level) in self.samples.iter_mut().zip(SAMPLE_LEVELS) {
+      let start = now();
+      let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), Compression::new(level));
+      let output = match encoder.write_all(data).and_then(|_| encoder.finish()) {
+        Ok(output) => output.len(),
+        Err(_) => data.len(),
+      };
+      sample.time += now() - start;
+      sample.input += data.len() as u64;
+      sample.output += output as u64;
+    }
+    self.sampled += data.len();
+    if !self.is_sampling() {
+      self.level = self.pick_from_samples();
+    }
+  }
+
+  /// Each sampled level in turn, kept while fast enough and compressing better than the one before.
+  fn pick_from_samples(&self) -> u32 {
+    let mut chosen = 0;
+    for index in 1..SAMPLE_LEVELS.len() {
+      let sample = &self.samples[index];
+      if sample.speed() < self.target * HEADROOM || !self.is_better(index, chosen) {
+        break;
+      }
+      chosen = index;
+    }
+    SAMPLE_LEVELS[chosen]
+  }
+
+  /// The sample `higher` saves enough output over the sample `lower`.
+  fn is_better(&self, higher: usize, lower: usize) -> bool {
+    self.samples[higher].ratio() <= self.samples[lower].ratio() * (1_f64 - MIN_RATIO_GAIN)
+  }
+
+  /// Account a file of `bytes` which took `time` ms from opening to its data descriptor, pauses excluded.
+  pub fn record(&mut self, bytes: u64, time: f64) {
+    if self.is_sampling() {
+      return;
+    }
+    self.window_bytes += bytes;
+    self.window_time += time;
+    if self.window_bytes < MIN_WINDOW {
+      return;
+    }
+
+    let speed = self.window_bytes as f64 / self.window_time.max(0.001_f64);
+    // levels past 6 pay off only if level 9 did in the samples, the same goes for levels past 1 and level 6
+    let worth_raising = if self.level < 6 { self.is_better(1, 0) } else { self.is_better(2, 1) };
+    if speed < self.target && self.level > 1 {
+      self.level -= 1;
+    } else if speed > self.target * HEADROOM && self.level < 9 && worth_raising {
+      self.level += 1;
+    }
+    self.window_bytes = 0;
+    self.window_time = 0_f64;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+
+  use super::*;
+
+  const MIB: u64 = 1024 * 1024;
+
+  /// `speeds` in byte/ms and `ratios`, one per sampled level
+  fn sampled(target: f64, speeds: [f64; 3], ratios: [f64; 3]) -> AutoLevel {
+    let mut auto_level = AutoLevel::new(target * 1000_f64);
+    for (index, sample) in auto_level.samples.iter_mut().enumerate() {
+      sample.input = SAMPLE_SIZE as u64;
+      sample.time = SAMPLE_SIZE as f64 / speeds[index];
+      sample.output = (SAMPLE_SIZE as f64 * ratios[index]) as u64;
+    }
+    auto_level.sampled = SAMPLE_SIZE;
+    auto_level.level = auto_level.pick_from_samples();
+    auto_level
+  }
+
+  #[test]
+  fn samples_pick_the_best_ratio_fast_enough() {
+    assert_eq!(sampled(1000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [0.40, 0.30, 0.298]).level(), 6);
+    assert_eq!(sampled(1000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [0.40, 0.30, 0.25]).level(), 9);
+    assert_eq!(sampled(10_000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [0.40, 0.30, 0.25]).level(), 6);
+    assert_eq!(sampled(50_000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [0.40, 0.30, 0.25]).level(), 1);
+    // already compressed data, nothing gained past level 1
+    assert_eq!(sampled(1000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [1.0, 0.999, 0.998]).level(), 1);
+  }
+
+  #[test]
+  fn level_follows_the_measured_speed() {
+    let mut auto_level = sampled(10_000_f64, [90_000_f64, 30_000_f64, 5_000_f64], [0.40, 0.30, 0.25]);
+    assert_eq!(auto_level.level(), 6);
+
+    // too slow, one step down once enough bytes are seen
+    auto_level.record(4 * MIB, 4.0 * MIB as f64 / 5_000_f64);
+    assert_eq!(auto_level.level(), 6);
+    auto_level.record(4 * MIB, 4.0 * MIB as f64 / 5_000_f64);
+    assert_eq!(auto_level.level(), 5);
+
+    // fast, back up
+    auto_level.record(8 * MIB, 8.0 * MIB as f64 / 20_000_f64);
+    assert_eq!(auto_level.level(), 6);
+    // within the headroom, unchanged
+    auto_level.record(8 * MIB, 8.0 * MIB as f64 / 11_000_f64);
+    assert_eq!(auto_level.level(), 6);
+  }
+
+  #[test]
+  fn sampling_stops_after_a_few_megabytes() {
+    let text: Vec<u8> = (0..200_000_u32).flat_map(|index| format!("{} {}\n", index, index % 17).into_bytes()).collect();
+    let clock = Cell::new(0_f64);
+    let now = || {
+      clock.set(clock.get() + 1_f64);
+      clock.get()
+    };
+
+    let mut auto_level = AutoLevel::new(1_f64);
+    while auto_level.is_sampling() {
+      auto_level.sample(&text, now);
+    }
+    assert_eq!(auto_level.samples[0].input, SAMPLE_SIZE as u64);
+    assert!(auto_level.samples[2].ratio() < auto_level.samples[0].ratio());
+    assert_ne!(auto_level.level(), 1);
+  }
+}
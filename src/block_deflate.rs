@@ -0,0 +1,129 @@
+//! Deflate a large file as independent blocks which concatenate into one raw deflate stream, like pigz.
+//!
+//! Each block is compressed with the 32 KiB before it as dictionary, so back references may cross block boundaries.
+//! The encoder is primed by compressing the dictionary and discarding the output: after a sync flush the output is
+//! byte aligned and the window is kept, so what follows continues any stream ending with the same 32 KiB.
+//! Every block but the last ends with a sync flush, the last one finishes the stream.
+
+use crc32fast::Hasher;
+use flate2::{Compress, CompressError, Compression, FlushCompress, Status};
+
+/// size of the deflate window, older bytes can never be referred to
+pub const DICTIONARY_SIZE: usize = 32 * 1024;
+
+/// Compress `block`, `dictionary` are the bytes right before it in the file, only its last 32 KiB are used.
+pub fn compress_block(dictionary: &[u8], block: &[u8], compression_level: u32, last: bool) -> Result<Vec<u8>, CompressError> {
+  let mut compress = Compress::new(Compression::new(compression_level), false);
+  let mut output = Vec::with_capacity(block.len() / 2 + 1024);
+
+  if !dictionary.is_empty() {
+    let dictionary = &dictionary[dictionary.len().saturating_sub(DICTIONARY_SIZE)..];
+    deflate(&mut compress, dictionary, &mut output, FlushCompress::Sync)?;
+    output.clear();
+  }
+
+  deflate(&mut compress, block, &mut output, if last { FlushCompress::Finish } else { FlushCompress::Sync })?;
+  Ok(output)
+}
+
+/// Feed all of `input`, then make sure everything up to `flush` is in `output`.
+fn deflate(compress: &mut Compress, mut input: &[u8], output: &mut Vec<u8>, flush: FlushCompress) -> Result<(), CompressError> {
+  loop {
+    if output.capacity() - output.len() < 1024 {
+      output.reserve((input.len() / 2).max(64 * 1024));
+    }
+    let total_in = compress.total_in();
+    let status = compress.compress_vec(input, output, flush)?;
+    input = &input[(compress.total_in() - total_in) as usize..];
+
+    let done = match flush {
+      FlushCompress::Finish => status == Status::StreamEnd,
+      // the flush is complete once the encoder stops short of filling the output
+      _ => input.is_empty() && output.len() < output.capacity(),
+    };
+    if done {
+      return Ok(());
+    }
+  }
+}
+
+/// CRC-32 of `a || b` from the CRC-32 of `a`, the CRC-32 of `b` and the length of `b`.
+pub fn combine_crc32(crc32_a: u32, crc32_b: u32, length_b: u64) -> u32 {
+  let mut hasher = Hasher::new_with_initial(crc32_a);
+  hasher.combine(&Hasher::new_with_initial_len(crc32_b, length_b));
+  hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+
+  use flate2::read::DeflateDecoder;
+
+  use super::*;
+
+  /// text repeating across block boundaries, so that back references into the dictionary pay off
+  fn sample(length: usize) -> Vec<u8> {
+    let mut sample = Vec::with_capacity(length);
+    let mut index = 0_u32;
+    while sample.len() < length {
+      sample.extend_from_slice(format!("line {} of a file compressed in blocks\n", index % 5000).as_bytes());
+      index = index.wrapping_mul(1103515245).wrapping_add(12345) >> 8;
+    }
+    sample.truncate(length);
+    sample
+  }
+
+  fn compress_in_blocks(content: &[u8], block_size: usize) -> (Vec<u8>, u32) {
+    let mut stream = Vec::new();
+    let mut crc32 = 0;
+    let mut offset = 0;
+    loop {
+      let end = (offset + block_size).min(content.len());
+      let last = end == content.len();
+      stream.extend(compress_block(&content[..offset], &content[offset..end], 6, last).unwrap());
+
+      let mut hasher = Hasher::new();
+      hasher.update(&content[offset..end]);
+      crc32 = combine_crc32(crc32, hasher.finalize(), (end - offset) as u64);
+
+      if last {
+        return (stream, crc32);
+      }
+      offset = end;
+    }
+  }
+
+  fn inflate(stream: &[u8]) -> Vec<u8> {
+    let mut content = Vec::new();
+    DeflateDecoder::new(stream).read_to_end(&mut content).unwrap();
+    content
+  }
+
+  #[test]
+  fn blocks_concatenate_into_one_stream() {
+    let content = sample(1_000_000);
+    for block_size in [DICTIONARY_SIZE / 3, 100_000, 1_000_000, 2_000_000] {
+      let (stream, crc32) = compress_in_blocks(&content, block_size);
+      assert_eq!(inflate(&stream), content);
+      assert_eq!(crc32, crc32fast::hash(&content));
+    }
+  }
+
+  #[test]
+  fn dictionary_keeps_the_ratio_close_to_a_single_stream() {
+    let content = sample(1_000_000);
+    let (single, _) = compress_in_blocks(&content, content.len());
+    let (blocks, _) = compress_in_blocks(&content, 64 * 1024);
+    let no_dictionary: usize = content.chunks(64 * 1024).map(|block| compress_block(&[], block, 6, false).unwrap().len()).sum();
+    assert!(blocks.len() < no_dictionary);
+    assert!(blocks.len() < single.len() + single.len() / 20);
+  }
+
+  #[test]
+  fn empty_file_is_a_finished_stream() {
+    let (stream, crc32) = compress_in_blocks(&[], 1024);
+    assert!(inflate(&stream).is_empty());
+    assert_eq!(crc32, 0);
+  }
+}
@@ -0,0 +1,193 @@
+//! Buffers used by compress jobs are sized from a single memory budget, and kept in `Context` to be reused by later jobs.
+
+use std::cell::RefCell;
+
+use crate::prelude::*;
+
+/// Reads 8 MiB at once, the buffers of a job take about 67 MiB and finished blocks of a worker pool the rest.
+pub const DEFAULT_MEMORY_BUDGET: usize = 96 * 1024 * 1024;
+pub const MIN_MEMORY_BUDGET: usize = 1024 * 1024;
+pub const MAX_MEMORY_BUDGET: usize = 1024 * 1024 * 1024;
+
+/// number of idle buffers kept of each kind, enough for a few concurrent jobs
+const MAX_IDLE_BUFFERS: usize = 8;
+/// js read buffers of a job: the chunk being copied, and the next read of the file or the first read of the next file
+pub const READ_BUFFERS: usize = 2;
+/// the budget is split in this many chunks, the buffers of a job take about 8.4 of them
+const BUDGET_CHUNKS: usize = 12;
+/// the local file header of each entry is put together in a buffer of this size
+pub const HEADER_BUFFER_SIZE: usize = 64 * 1024;
+/// blocks sent to a worker pool are not made smaller than this, the 32 KiB dictionary of each block would cost too much
+#[cfg(any(feature = "worker-pool", test))]
+const MIN_POOL_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Buffer sizes of one job, unit: byte.
+///
+/// A job holds all of these at once, `job_bytes` adds them up: the wasm read buffer and `READ_BUFFERS` js read buffers
+/// of a chunk each, the deflate output, the write buffer, output queued in the writer up to the high-water mark, the
+/// central directory buffer and the header buffer. Finished blocks of a worker pool take what is left of the budget.
+/// A `highWaterMark` given to the job replaces the default one, and a codec per extra level picked by rules adds an output buffer.
+#[derive(Clone, Copy)]
+pub struct MemoryBudget {
+  /// clamped into `MIN_MEMORY_BUDGET..=MAX_MEMORY_BUDGET`
+  #[cfg(any(feature = "worker-pool", test))]
+  budget: usize,
+  /// size of each read
+  pub chunk_size: usize,
+  /// buffers taking the output of a chunk at once, with room for deflate growing incompressible data
+  /// and for output held back from the previous chunk
+  pub output_size: usize,
+  /// output is sent to the writer in pieces of this size, once a piece is buffered
+  pub write_threshold: usize,
+  /// the write buffer holds less than a piece, and the output of a chunk added at once
+  pub result_buffer_size: usize,
+  /// output written but not accepted by the writer yet
+  pub high_water_mark: usize,
+  /// the central directory is sent to the writer in pieces of this size, the buffer has room for one more record
+  pub central_directory_chunk_size: usize,
+}
+
+impl MemoryBudget {
+  /// `budget` is clamped into `MIN_MEMORY_BUDGET..=MAX_MEMORY_BUDGET`.
+  pub fn new(budget: usize) -> Self {
+    let budget = budget.clamp(MIN_MEMORY_BUDGET, MAX_MEMORY_BUDGET);
+    let chunk_size = budget / BUDGET_CHUNKS;
+    let output_size = chunk_size + chunk_size / 8;
+    Self {
+      #[cfg(any(feature = "worker-pool", test))]
+      budget,
+      chunk_size,
+      output_size,
+      write_threshold: chunk_size,
+      result_buffer_size: chunk_size + output_size,
+      high_water_mark: chunk_size,
+      central_directory_chunk_size: chunk_size,
+    }
+  }
+
+  /// Bytes held by the buffers of a job, worker pool blocks excluded.
+  pub fn job_bytes(&self) -> usize {
+    // the wasm read buffer and the js read buffers
+    (1 + READ_BUFFERS) * self.chunk_size
+      // the deflate output
+      + self.output_size
+      + self.result_buffer_size
+      + self.high_water_mark
+      // the central directory buffer
+      + self.output_size
+      + HEADER_BUFFER_SIZE
+  }
+
+  /// Block size and number of blocks in flight of a job compressing on a pool of `workers`.
+  ///
+  /// The at most `2 * depth` finished blocks a job holds take what the buffers of the job leave of the budget.
+  /// Blocks shrink first so that every worker stays busy, then the depth.
+  #[cfg(any(feature = "worker-pool", test))]
+  pub fn pool_blocks(&self, workers: usize) -> (usize, usize) {
+    let share = self.budget - self.job_bytes();
+    let depth = (workers * 2).max(1);
+    let block_size = (share / (2 * depth)).clamp(MIN_POOL_BLOCK_SIZE.min(self.chunk_size), self.chunk_size);
+    (block_size, depth.min(share / (2 * block_size)).max(1))
+  }
+}
+
+impl Default for MemoryBudget {
+  fn default() -> Self {
+    Self::new(DEFAULT_MEMORY_BUDGET)
+  }
+}
+
+/// Idle buffers left by finished jobs.
+#[derive(Default)]
+pub struct BufferPool {
+  vecs: RefCell<Vec<Vec<u8>>>,
+  array_buffers: RefCell<Vec<js_sys::ArrayBuffer>>,
+}
+
+impl BufferPool {
+  /// An empty `Vec` with at least `capacity`, the smallest idle one which is large enough is reused.
+  pub fn take_vec(&self, capacity: usize) -> Vec<u8> {
+    let mut vecs = self.vecs.borrow_mut();
+    let index = vecs.iter()
+      .enumerate()
+      .filter(|(_, vec)| vec.capacity() >= capacity)
+      .min_by_key(|(_, vec)| vec.capacity())
+      .map(|(index, _)| index);
+    match index {
+      Some(index) => vecs.swap_remove(index),
+      None => Vec::with_capacity(capacity),
+    }
+  }
+
+  pub fn give_vec(&self, mut vec: Vec<u8>) {
+    let mut vecs = self.vecs.borrow_mut();
+    if vecs.len() < MAX_IDLE_BUFFERS {
+      vec.clear();
+      vecs.push(vec);
+    }
+  }
+
+  /// An `ArrayBuffer` of exactly `byte_length`, as BYOB reads fill the whole buffer.
+  pub fn take_array_buffer(&self, byte_length: usize) -> js_sys::ArrayBuffer {
+    let mut array_buffers = self.array_buffers.borrow_mut();
+    match array_buffers.iter().position(|array_buffer| array_buffer.byte_length() as usize == byte_length) {
+      Some(index) => array_buffers.swap_remove(index),
+      None => js_sys::ArrayBuffer::new(byte_length as u32),
+    }
+  }
+
+  /// `array_buffer` must not be detached.
+  pub fn give_array_buffer(&self, array_buffer: js_sys::ArrayBuffer) {
+    let mut array_buffers = self.array_buffers.borrow_mut();
+    if array_buffers.len() < MAX_IDLE_BUFFERS {
+      array_buffers.push(array_buffer);
+    }
+  }
+
+  /// Drop every idle buffer.
+  pub fn clear(&self) {
+    self.vecs.borrow_mut().clear();
+    self.array_buffers.borrow_mut().clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn buffers_of_a_job_stay_within_the_budget() {
+    for budget in [MIN_MEMORY_BUDGET, 8 * 1024 * 1024, DEFAULT_MEMORY_BUDGET, MAX_MEMORY_BUDGET] {
+      let MemoryBudget { chunk_size, output_size, write_threshold, result_buffer_size, high_water_mark, central_directory_chunk_size, .. } = MemoryBudget::new(budget);
+      // what `ArchiveJob::run` takes: the wasm read buffer, the js read buffers, the central directory buffer,
+      // the deflate output, the write buffer, then the queued writes and the header buffer of `write_archive`
+      let job_bytes = chunk_size + READ_BUFFERS * chunk_size + output_size + output_size + result_buffer_size + high_water_mark + HEADER_BUFFER_SIZE;
+      assert_eq!(job_bytes, MemoryBudget::new(budget).job_bytes());
+      assert!(job_bytes <= budget, "{} bytes", budget);
+
+      // the write buffer keeps less than a piece and takes the output of a chunk, which fits the central directory too
+      assert!(result_buffer_size >= write_threshold + output_size && output_size > chunk_size.max(central_directory_chunk_size));
+    }
+
+    // a larger budget than allowed is clamped
+    assert!(MemoryBudget::new(usize::MAX).job_bytes() <= MAX_MEMORY_BUDGET);
+  }
+
+  #[test]
+  fn pool_blocks_stay_within_the_budget() {
+    for budget in [MIN_MEMORY_BUDGET, 8 * 1024 * 1024, DEFAULT_MEMORY_BUDGET, MAX_MEMORY_BUDGET] {
+      let memory_budget = MemoryBudget::new(budget);
+      for workers in [1, 2, 8, 64] {
+        let (block_size, depth) = memory_budget.pool_blocks(workers);
+        assert!(memory_budget.job_bytes() + 2 * depth * block_size <= budget, "{} bytes, {} workers", budget, workers);
+        assert!(block_size <= memory_budget.chunk_size && depth >= 1 && depth <= 2 * workers);
+      }
+    }
+
+    // blocks shrink to keep 8 workers busy
+    let memory_budget = MemoryBudget::default();
+    assert_eq!(memory_budget.pool_blocks(8), ((DEFAULT_MEMORY_BUDGET - memory_budget.job_bytes()) / 32, 16));
+    // not below the minimum block size, fewer blocks are in flight instead
+    assert_eq!(MemoryBudget::new(MIN_MEMORY_BUDGET).pool_blocks(8), (MIN_POOL_BLOCK_SIZE, 1));
+  }
+}
@@ -0,0 +1,192 @@
+//! The type `CentralDirectory` keeps what the central directory needs to know about every written entry, compactly.
+//!
+//! All names share one `String`, and each entry is a fixed size record, so no allocation is made per entry.
+//! Central directory headers are only generated when the tail is written, a chunk at a time.
+
+use crate::appnote63::FileHeader;
+use crate::codec::EntryCodec;
+
+struct Record {
+  lfh_pos: u64,
+  compressed_size: u64,
+  uncompressed_size: u64,
+  /// end of the name in `CentralDirectory.names`, it starts where the previous name ends
+  name_end: usize,
+  crc32: u32,
+  is_dir: bool,
+  codec: EntryCodec,
+}
+
+/// An entry as seen through `CentralDirectory::entries`.
+pub struct EntryRef<'a> {
+  /// directories end with a slash
+  pub name: &'a str,
+  pub crc32: u32,
+  pub compressed_size: u64,
+  pub uncompressed_size: u64,
+  pub codec: EntryCodec,
+}
+
+#[derive(Default)]
+pub struct CentralDirectory {
+  names: String,
+  records: Vec<Record>,
+}
+
+impl CentralDirectory {
+  pub fn with_capacity(number_of_entries: usize) -> Self {
+    Self {
+      names: String::new(),
+      records: Vec::with_capacity(number_of_entries),
+    }
+  }
+
+  /// Record a written entry, `name` is the entry name as in its local file header, directories end with a slash.
+  pub fn push(&mut self, name: &str, lfh_pos: u64, codec: EntryCodec, crc32: u32, compressed_size: u64, uncompressed_size: u64) {
+    let is_dir = name.ends_with('/');
+    self.names.push_str(name);
+    self.records.push(Record { lfh_pos, compressed_size, uncompressed_size, name_end: self.names.len(), crc32, is_dir, codec });
+  }
+
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+
+  fn name(&self, index: usize) -> &str {
+    let start = if index == 0 { 0 } else { self.records[index - 1].name_end };
+    &self.names[start..self.records[index].name_end]
+  }
+
+  pub fn entries(&self) -> impl Iterator<Item = EntryRef<'_>> {
+    self.records.iter().enumerate().map(move |(index, record)| EntryRef {
+      name: self.name(index),
+      crc32: record.crc32,
+      compressed_size: record.compressed_size,
+      uncompressed_size: record.uncompressed_size,
+      codec: record.codec,
+    })
+  }
+
+  /// Append central directory headers starting from entry `start`, until `buffer` holds at least `chunk_size` bytes
+  /// or every entry is written, returns the index of the next entry to write.
+  pub fn write_chunk(&self, start: usize, buffer: &mut Vec<u8>, chunk_size: usize) -> usize {
+    let mut index = start;
+    while index < self.records.len() && buffer.len() < chunk_size {
+      let record = &self.records[index];
+      let mut file_header = FileHeader::new(self.name(index).to_owned(), record.lfh_pos, record.is_dir);
+      file_header.set_crc_32(record.crc32);
+      file_header.set_compressed_size_u64(record.compressed_size);
+      file_header.set_uncompressed_size_u64(record.uncompressed_size);
+      if !record.is_dir {
+        file_header.set_compression_method(record.codec.kind.method());
+      }
+      file_header.write_into_as_cdh(buffer);
+      index += 1;
+    }
+    index
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryInto;
+
+  use crate::codec::CodecKind;
+
+  use super::*;
+
+  const DEFLATED: EntryCodec = EntryCodec { kind: CodecKind::Flate2, level: 6 };
+
+  const NUMBER_OF_ENTRIES: usize = 1_000_000;
+  const CHUNK_SIZE: usize = 64 * 1024;
+
+  fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+  }
+
+  fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+  }
+
+  /// one directory followed by 999 files, repeated
+  fn synthetic_plan() -> CentralDirectory {
+    let mut central_directory = CentralDirectory::with_capacity(NUMBER_OF_ENTRIES);
+    for index in 0..NUMBER_OF_ENTRIES {
+      if index % 1000 == 0 {
+        central_directory.push(&format!("dir-{}/", index / 1000), index as u64 * 100, EntryCodec::STORED, 0, 0, 0);
+      } else {
+        central_directory.push(&format!("dir-{}/file-{}.txt", index / 1000, index), index as u64 * 100, DEFLATED, index as u32, 42, 1000);
+      }
+    }
+    central_directory
+  }
+
+  #[test]
+  fn records_stay_compact() {
+    assert!(std::mem::size_of::<Record>() <= 48);
+  }
+
+  #[test]
+  fn million_entries_are_written_in_bounded_chunks() {
+    let central_directory = synthetic_plan();
+    assert_eq!(central_directory.len(), NUMBER_OF_ENTRIES);
+
+    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+    let mut next = 0;
+    let mut entries_seen = 0;
+    let mut chunks = 0;
+    while next < central_directory.len() {
+      buffer.clear();
+      next = central_directory.write_chunk(next, &mut buffer, CHUNK_SIZE);
+      chunks += 1;
+
+      // a chunk only goes past its size by the last header
+      assert!(buffer.len() < CHUNK_SIZE + 46 + 64 + 32);
+
+      // every chunk ends on a header boundary
+      let mut offset = 0;
+      while offset < buffer.len() {
+        assert_eq!(read_u32(&buffer, offset), 0x02014b50);
+        let name_length = read_u16(&buffer, offset + 28) as usize;
+        let extra_field_length = read_u16(&buffer, offset + 30) as usize;
+        let name = std::str::from_utf8(&buffer[offset + 46..offset + 46 + name_length]).unwrap();
+        if entries_seen % 1000 == 0 {
+          assert_eq!(name, format!("dir-{}/", entries_seen / 1000));
+          assert_eq!(extra_field_length, 16);
+        } else {
+          assert_eq!(name, format!("dir-{}/file-{}.txt", entries_seen / 1000, entries_seen));
+          assert_eq!(read_u32(&buffer, offset + 16), entries_seen as u32);
+          assert_eq!(read_u16(&buffer, offset + 10), 8);
+          assert_eq!(extra_field_length, 32);
+        }
+        offset += 46 + name_length + extra_field_length;
+        entries_seen += 1;
+      }
+      assert_eq!(offset, buffer.len());
+    }
+
+    assert_eq!(entries_seen, NUMBER_OF_ENTRIES);
+    assert!(chunks > 1);
+  }
+
+  #[test]
+  fn entries_keep_names_and_sizes() {
+    let mut central_directory = CentralDirectory::default();
+    central_directory.push("a/", 0, EntryCodec::STORED, 0, 0, 0);
+    central_directory.push("a/b.txt", 30, DEFLATED, 7, 5, 9);
+    central_directory.push("a/c.jpg", 90, EntryCodec::STORED, 3, 9, 9);
+
+    let entries: Vec<EntryRef> = central_directory.entries().collect();
+    assert_eq!(entries[0].name, "a/");
+    assert_eq!(entries[0].crc32, 0);
+    assert_eq!(entries[1].name, "a/b.txt");
+    assert_eq!((entries[1].crc32, entries[1].compressed_size, entries[1].uncompressed_size), (7, 5, 9));
+    assert_eq!(entries[1].codec, DEFLATED);
+    assert_eq!(entries[2].codec, EntryCodec::STORED);
+
+    // a stored file says so in its header
+    let mut buffer = Vec::new();
+    central_directory.write_chunk(2, &mut buffer, 1);
+    assert_eq!(read_u16(&buffer, 10), 0);
+  }
+}
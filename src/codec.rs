@@ -0,0 +1,541 @@
+//! The trait `Codec` is the deflate backend of the compress loop, implemented with `flate2` and with the browser's
+//! `CompressionStream("deflate-raw")`.
+//!
+//! With the `ultra` feature, level 10 deflates with Zopfli. The "store" codec copies entries as they are.
+//!
+//! All of them produce a raw deflate stream per entry, the compress loop computes the CRC-32 and counts the sizes itself.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use flate2::{write::DeflateEncoder, Compression};
+use futures::channel::oneshot;
+use futures::future::LocalBoxFuture;
+
+use crate::constant::{COMPRESSION_METHOD, COMPRESSION_METHOD_STORE};
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+#[wasm_bindgen]
+extern "C" {
+  /// declared here as `web_sys::CompressionStream` requires `web_sys_unstable_apis`
+  type CompressionStream;
+
+  #[wasm_bindgen(constructor, catch)]
+  fn new(format: &str) -> Result<CompressionStream, JsValue>;
+
+  #[wasm_bindgen(method, getter)]
+  fn readable(this: &CompressionStream) -> web_sys::ReadableStream;
+
+  #[wasm_bindgen(method, getter)]
+  fn writable(this: &CompressionStream) -> web_sys::WritableStream;
+
+  type ReadResultJS;
+
+  #[wasm_bindgen(method, getter)]
+  fn value(this: &ReadResultJS) -> JsValue;
+
+  #[wasm_bindgen(method, getter)]
+  fn done(this: &ReadResultJS) -> JsValue;
+}
+
+/// level beyond 9, deflates with Zopfli
+pub const ULTRA_COMPRESSION_LEVEL: u32 = 10;
+#[cfg(feature = "ultra")]
+pub const MAX_COMPRESSION_LEVEL: u32 = ULTRA_COMPRESSION_LEVEL;
+#[cfg(not(feature = "ultra"))]
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
+
+/// Zopfli iterations per block by default, more give a slightly smaller output in proportionally more time
+pub const DEFAULT_ULTRA_ITERATIONS: u64 = 15;
+pub const MAX_ULTRA_ITERATIONS: u64 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CodecKind {
+  Flate2,
+  /// `CompressionStream("deflate-raw")`
+  Native,
+  /// only chosen by `ULTRA_COMPRESSION_LEVEL`
+  Zopfli,
+  /// no compression, method 0
+  Store,
+}
+
+impl CodecKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Flate2 => "flate2",
+      Self::Native => "native",
+      Self::Zopfli => "zopfli",
+      Self::Store => "store",
+    }
+  }
+
+  /// Compression method in the zip headers.
+  pub fn method(&self) -> u16 {
+    match self {
+      Self::Store => COMPRESSION_METHOD_STORE,
+      _ => COMPRESSION_METHOD,
+    }
+  }
+
+  /// Codecs which can be asked for, Zopfli is chosen by the level.
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "flate2" => Some(Self::Flate2),
+      "native" => Some(Self::Native),
+      "store" => Some(Self::Store),
+      _ => None,
+    }
+  }
+}
+
+/// How an entry was compressed, kept per entry for the summary.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EntryCodec {
+  pub kind: CodecKind,
+  /// `0` when stored or deflated by `CompressionStream`, which has no level
+  pub level: u8,
+}
+
+impl EntryCodec {
+  /// directories
+  pub const STORED: Self = Self { kind: CodecKind::Store, level: 0 };
+}
+
+/// Compresses one entry after another into raw deflate streams.
+pub trait Codec {
+  fn kind(&self) -> CodecKind;
+
+  /// Feed `input`, the returned output is valid until the next call.
+  fn compress<'a>(&'a mut self, input: &'a [u8]) -> LocalBoxFuture<'a, Result<&'a [u8], WasmError>>;
+
+  /// End the stream of the current entry, the next `compress` starts a new one.
+  fn finish(&mut self) -> LocalBoxFuture<'_, Result<&[u8], WasmError>>;
+
+  /// Give back the output buffer so that it can be reused.
+  fn into_buffer(self: Box<Self>) -> Vec<u8>;
+}
+
+/// `CompressionStream` has no level, the native codec is used for levels 1 to 9 if the browser supports "deflate-raw",
+/// `flate2` otherwise. Unless storing, `ULTRA_COMPRESSION_LEVEL` always uses Zopfli with `ultra_iterations`.
+pub fn create_codec(kind: CodecKind, compression_level: u32, ultra_iterations: u64, buffer: Vec<u8>) -> Box<dyn Codec> {
+  if kind == CodecKind::Store {
+    return Box::new(StoreCodec { buffer });
+  }
+  #[cfg(feature = "ultra")]
+  if compression_level == ULTRA_COMPRESSION_LEVEL {
+    return Box::new(ZopfliCodec::new(ultra_iterations, buffer));
+  }
+  #[cfg(not(feature = "ultra"))]
+  let _ = ultra_iterations;
+
+  match kind {
+    CodecKind::Native if (1..ULTRA_COMPRESSION_LEVEL).contains(&compression_level) && NativeCodec::is_supported() => Box::new(NativeCodec::new(buffer)),
+    _ => Box::new(Flate2Codec::new(compression_level, buffer)),
+  }
+}
+
+/// The codecs of a job, one per codec and level asked for, created on first use.
+pub struct Codecs {
+  ultra_iterations: u64,
+  codecs: Vec<(CodecKind, u32, Box<dyn Codec>)>,
+  /// given to the first codec, the others grow their own
+  buffer: Option<Vec<u8>>,
+}
+
+impl Codecs {
+  pub fn new(ultra_iterations: u64, buffer: Vec<u8>) -> Self {
+    Self { ultra_iterations, codecs: Vec::new(), buffer: Some(buffer) }
+  }
+
+  pub fn get(&mut self, kind: CodecKind, compression_level: u32) -> &mut dyn Codec {
+    let index = match self.codecs.iter().position(|(codec_kind, level, _)| *codec_kind == kind && *level == compression_level) {
+      Some(index) => index,
+      None => {
+        let buffer = self.buffer.take().unwrap_or_default();
+        self.codecs.push((kind, compression_level, create_codec(kind, compression_level, self.ultra_iterations, buffer)));
+        self.codecs.len() - 1
+      },
+    };
+    self.codecs[index].2.as_mut()
+  }
+
+  /// Give back the output buffers so that they can be reused.
+  pub fn into_buffers(self) -> impl Iterator<Item = Vec<u8>> {
+    self.codecs.into_iter().map(|(_, _, codec)| codec.into_buffer()).chain(self.buffer)
+  }
+}
+
+/// Entries copied as they are, the compressed size is the uncompressed size.
+pub struct StoreCodec {
+  buffer: Vec<u8>,
+}
+
+impl Codec for StoreCodec {
+  fn kind(&self) -> CodecKind {
+    CodecKind::Store
+  }
+
+  fn compress<'a>(&'a mut self, input: &'a [u8]) -> LocalBoxFuture<'a, Result<&'a [u8], WasmError>> {
+    Box::pin(async move { Ok(input) })
+  }
+
+  fn finish(&mut self) -> LocalBoxFuture<'_, Result<&[u8], WasmError>> {
+    Box::pin(async move { Ok(&[][..]) })
+  }
+
+  fn into_buffer(self: Box<Self>) -> Vec<u8> {
+    self.buffer
+  }
+}
+
+pub struct Flate2Codec {
+  encoder: DeflateEncoder<Vec<u8>>,
+  finished: bool,
+}
+
+impl Flate2Codec {
+  pub fn new(compression_level: u32, buffer: Vec<u8>) -> Self {
+    Self { encoder: DeflateEncoder::new(buffer, Compression::new(compression_level)), finished: false }
+  }
+
+  fn compress_sync(&mut self, input: &[u8]) -> Result<&[u8], WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::compress_sync);
+
+    if self.finished {
+      // a new stream, writing into the same buffer
+      let buffer = std::mem::take(self.encoder.get_mut());
+      if let Err(e) = self.encoder.reset(buffer) {
+        return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+      }
+      self.finished = false;
+    }
+    self.encoder.get_mut().clear();
+    if let Err(e) = self.encoder.write_all(input) {
+      return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+    }
+    Ok(self.encoder.get_ref())
+  }
+
+  fn finish_sync(&mut self) -> Result<&[u8], WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::finish_sync);
+
+    if self.finished {
+      // nothing was fed since the last entry, still a valid empty stream is needed
+      self.compress_sync(&[])?;
+    }
+    self.encoder.get_mut().clear();
+    if let Err(e) = self.encoder.try_finish() {
+      return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+    }
+    self.finished = true;
+    Ok(self.encoder.get_ref())
+  }
+}
+
+impl Codec for Flate2Codec {
+  fn kind(&self) -> CodecKind {
+    CodecKind::Flate2
+  }
+
+  fn compress<'a>(&'a mut self, input: &'a [u8]) -> LocalBoxFuture<'a, Result<&'a [u8], WasmError>> {
+    Box::pin(async move { self.compress_sync(input) })
+  }
+
+  fn finish(&mut self) -> LocalBoxFuture<'_, Result<&[u8], WasmError>> {
+    Box::pin(async move { self.finish_sync() })
+  }
+
+  fn into_buffer(self: Box<Self>) -> Vec<u8> {
+    self.encoder.finish().unwrap_or_default()
+  }
+}
+
+/// One `CompressionStream` per entry, its readable side is drained by a task of its own so that writes never stall.
+struct NativeStream {
+  writer: web_sys::WritableStreamDefaultWriter,
+  /// output read so far and not returned yet
+  output: Rc<RefCell<Vec<u8>>>,
+  /// settled once the readable side is done
+  drained: oneshot::Receiver<Result<(), WasmError>>,
+}
+
+pub struct NativeCodec {
+  stream: Option<NativeStream>,
+  buffer: Vec<u8>,
+}
+
+impl NativeCodec {
+  pub fn new(buffer: Vec<u8>) -> Self {
+    Self { stream: None, buffer }
+  }
+
+  /// `CompressionStream` exists and knows "deflate-raw", it is missing in older browsers. Checked once per thread.
+  pub fn is_supported() -> bool {
+    thread_local! {
+      static SUPPORTED: bool = CompressionStream::new("deflate-raw").is_ok();
+    }
+    SUPPORTED.with(|supported| *supported)
+  }
+
+  fn open(&mut self) -> Result<&NativeStream, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::open);
+
+    if self.stream.is_none() {
+      let compression_stream = match CompressionStream::new("deflate-raw") {
+        Ok(compression_stream) => compression_stream,
+        Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
+      };
+      let writer = match compression_stream.writable().get_writer() {
+        Ok(writer) => writer,
+        Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
+      };
+      let reader = compression_stream.readable().get_reader().unchecked_into::<web_sys::ReadableStreamDefaultReader>();
+
+      let output = Rc::new(RefCell::new(Vec::new()));
+      let (sender, drained) = oneshot::channel();
+      let task_output = Rc::clone(&output);
+      wasm_bindgen_futures::spawn_local(async move {
+        let _ = sender.send(Self::drain(reader, task_output).await);
+      });
+      self.stream = Some(NativeStream { writer, output, drained });
+    }
+    Ok(self.stream.as_ref().unwrap())
+  }
+
+  async fn drain(reader: web_sys::ReadableStreamDefaultReader, output: Rc<RefCell<Vec<u8>>>) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::drain);
+
+    loop {
+      let read_result = match utils::await_promise(reader.read()).await {
+        Ok(read_result) => read_result.unchecked_into::<ReadResultJS>(),
+        Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
+      };
+      if read_result.done().eq(&JsValue::TRUE) {
+        return Ok(());
+      }
+      let Ok(chunk) = read_result.value().dyn_into::<js_sys::Uint8Array>() else {
+        return Err(WasmError::dynamic_cast_error(LOCATION, "JsValue", "Uint8Array"));
+      };
+      let mut output = output.borrow_mut();
+      let length = output.len();
+      output.resize(length + chunk.length() as usize, 0_u8);
+      chunk.copy_to(&mut output[length..]);
+    }
+  }
+
+  /// Move the output read so far into `buffer`.
+  fn take_output(buffer: &mut Vec<u8>, stream: &NativeStream) {
+    buffer.clear();
+    buffer.append(&mut stream.output.borrow_mut());
+  }
+}
+
+impl Codec for NativeCodec {
+  fn kind(&self) -> CodecKind {
+    CodecKind::Native
+  }
+
+  fn compress<'a>(&'a mut self, input: &'a [u8]) -> LocalBoxFuture<'a, Result<&'a [u8], WasmError>> {
+    Box::pin(async move {
+      #[allow(non_snake_case)]
+      let LOCATION = utils::type_name(&Self::compress);
+
+      let stream = self.open()?;
+      // the stream keeps the chunk, it must not be a view of a buffer which is reused
+      let chunk = js_sys::Uint8Array::from(input);
+      if let Err(e) = utils::await_promise(stream.writer.write_with_chunk(&chunk)).await {
+        return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+      }
+      let stream = self.stream.as_ref().unwrap();
+      Self::take_output(&mut self.buffer, stream);
+      Ok(&self.buffer[..])
+    })
+  }
+
+  fn finish(&mut self) -> LocalBoxFuture<'_, Result<&[u8], WasmError>> {
+    Box::pin(async move {
+      #[allow(non_snake_case)]
+      let LOCATION = utils::type_name(&Self::finish);
+
+      self.open()?;
+      let NativeStream { writer, output, drained } = self.stream.take().unwrap();
+      if let Err(e) = utils::await_promise(writer.close()).await {
+        return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+      }
+      match drained.await {
+        Ok(result) => result?,
+        Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
+      }
+      self.buffer.clear();
+      self.buffer.append(&mut output.borrow_mut());
+      Ok(&self.buffer[..])
+    })
+  }
+
+  fn into_buffer(self: Box<Self>) -> Vec<u8> {
+    self.buffer
+  }
+}
+
+/// Zopfli output of the current entry, shared with the encoder which owns its sink.
+#[cfg(feature = "ultra")]
+struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+#[cfg(feature = "ultra")]
+impl Write for SharedSink {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0.borrow_mut().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Deflates with Zopfli in pieces of `ZOPFLI_CHUNK_SIZE`, each one with the 32 KiB window of the previous one.
+#[cfg(feature = "ultra")]
+pub struct ZopfliCodec {
+  options: zopfli::Options,
+  encoder: Option<zopfli::DeflateEncoder<SharedSink>>,
+  /// input not fed to the encoder yet, less than a piece
+  pending: Vec<u8>,
+  output: Rc<RefCell<Vec<u8>>>,
+  buffer: Vec<u8>,
+}
+
+/// the piece size Zopfli's own buffered encoder uses
+#[cfg(feature = "ultra")]
+const ZOPFLI_CHUNK_SIZE: usize = 1_000_000;
+
+#[cfg(feature = "ultra")]
+impl ZopfliCodec {
+  pub fn new(iterations: u64, buffer: Vec<u8>) -> Self {
+    let options = zopfli::Options {
+      iteration_count: std::num::NonZeroU64::new(iterations.max(1)).unwrap(),
+      ..zopfli::Options::default()
+    };
+    Self { options, encoder: None, pending: Vec::new(), output: Rc::new(RefCell::new(Vec::new())), buffer }
+  }
+
+  fn feed(&mut self, input: &[u8], last: bool) -> Result<&[u8], WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::feed);
+
+    let Self { options, encoder, pending, output, buffer } = self;
+    let encoder = encoder.get_or_insert_with(|| {
+      zopfli::DeflateEncoder::new(*options, zopfli::BlockType::Dynamic, SharedSink(Rc::clone(output)))
+    });
+
+    pending.extend_from_slice(input);
+    let mut offset = 0;
+    while pending.len() - offset >= ZOPFLI_CHUNK_SIZE || (last && offset < pending.len()) {
+      let end = (offset + ZOPFLI_CHUNK_SIZE).min(pending.len());
+      if let Err(e) = encoder.write_all(&pending[offset..end]) {
+        return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+      }
+      offset = end;
+    }
+    pending.drain(..offset);
+
+    if last {
+      if let Some(encoder) = self.encoder.take() {
+        if let Err(e) = encoder.finish() {
+          return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
+        }
+      }
+    }
+    buffer.clear();
+    buffer.append(&mut output.borrow_mut());
+    Ok(&self.buffer[..])
+  }
+}
+
+#[cfg(feature = "ultra")]
+impl Codec for ZopfliCodec {
+  fn kind(&self) -> CodecKind {
+    CodecKind::Zopfli
+  }
+
+  fn compress<'a>(&'a mut self, input: &'a [u8]) -> LocalBoxFuture<'a, Result<&'a [u8], WasmError>> {
+    Box::pin(async move { self.feed(input, false) })
+  }
+
+  fn finish(&mut self) -> LocalBoxFuture<'_, Result<&[u8], WasmError>> {
+    Box::pin(async move { self.feed(&[], true) })
+  }
+
+  fn into_buffer(self: Box<Self>) -> Vec<u8> {
+    self.buffer
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+
+  use flate2::read::DeflateDecoder;
+
+  use super::*;
+
+  fn compress(codec: &mut Flate2Codec, chunks: &[&[u8]]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for chunk in chunks {
+      stream.extend_from_slice(codec.compress_sync(chunk).unwrap());
+    }
+    stream.extend_from_slice(codec.finish_sync().unwrap());
+    stream
+  }
+
+  fn inflate(stream: &[u8]) -> Vec<u8> {
+    let mut content = Vec::new();
+    DeflateDecoder::new(stream).read_to_end(&mut content).unwrap();
+    content
+  }
+
+  #[test]
+  fn flate2_codec_starts_a_new_stream_per_entry() {
+    let mut codec = Flate2Codec::new(6, Vec::new());
+    let first = compress(&mut codec, &[b"first entry, ", b"in two chunks"]);
+    let second = compress(&mut codec, &[b"second entry"]);
+    let empty = compress(&mut codec, &[]);
+    assert_eq!(inflate(&first), b"first entry, in two chunks");
+    assert_eq!(inflate(&second), b"second entry");
+    assert!(inflate(&empty).is_empty());
+  }
+
+  #[cfg(feature = "ultra")]
+  #[test]
+  fn zopfli_codec_is_smaller_than_level_9() {
+    let content: Vec<u8> = (0..40_000_u32).flat_map(|index| format!("{} ", index % 977 * 31 % 1000).into_bytes()).collect();
+    let mut flate2 = Flate2Codec::new(9, Vec::new());
+    let level_9 = compress(&mut flate2, &[&content]);
+
+    let mut zopfli = ZopfliCodec::new(2, Vec::new());
+    let mut ultra = Vec::new();
+    for chunk in content.chunks(50_000) {
+      ultra.extend_from_slice(zopfli.feed(chunk, false).unwrap());
+    }
+    ultra.extend_from_slice(zopfli.feed(&[], true).unwrap());
+    assert_eq!(inflate(&ultra), content);
+    assert!(ultra.len() < level_9.len());
+
+    // the next entry is a new stream, an empty one too
+    assert!(inflate(zopfli.feed(&[], true).unwrap()).is_empty());
+  }
+
+  #[test]
+  fn codec_names_round_trip() {
+    for kind in [CodecKind::Flate2, CodecKind::Native, CodecKind::Store] {
+      assert_eq!(CodecKind::from_name(kind.name()), Some(kind));
+    }
+    assert_eq!(CodecKind::from_name("zlib"), None);
+  }
+}
@@ -0,0 +1,389 @@
+//! The type `CompressOptions` is the validated form of the options object accepted by `compress_with_options`.
+
+use crate::auto_level::DEFAULT_TARGET_SPEED;
+use crate::codec::{CodecKind, DEFAULT_ULTRA_ITERATIONS, MAX_COMPRESSION_LEVEL, MAX_ULTRA_ITERATIONS};
+use crate::compress_rules::CompressRules;
+use crate::buffer_pool::{MemoryBudget, MAX_MEMORY_BUDGET, MIN_MEMORY_BUDGET};
+use crate::read_ahead::{DEFAULT_READ_AHEAD, MAX_READ_AHEAD};
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_COMPRESS_OPTIONS: &'static str = r#"
+export interface CompressOptions {
+  /** name of output zip file, ".zip" is appended if missing */
+  fileName: string;
+  /**
+   * an integer from 0 to 9, 6 by default, 10 deflates with Zopfli if built with the "ultra" feature,
+   * "auto" picks a level from 1 to 9 per file, the best ratio keeping `targetSpeed`
+   */
+  level?: number | "auto";
+  /** once aborted the writer is aborted too */
+  signal?: AbortSignal;
+  /** how often "average_speed", "current_speed" and "eta" are invoked, unit: ms, 5000 by default */
+  speedReportInterval?: number;
+  /**
+   * bytes of buffers a job may hold, from 1 MiB to 1 GiB, 96 MiB by default,
+   * the read size, the write threshold and the central directory buffer are derived from it
+   */
+  memoryBudget?: number;
+  /** number of files looked up ahead while compressing, from 0 to 64, 4 by default, 0 also stops reading the next file early */
+  readAhead?: number;
+  /**
+   * bytes of output written but not accepted by the writer yet, from 0 to 1 GiB, the write threshold by default,
+   * compression goes on meanwhile, with 0 every write is awaited
+   */
+  highWaterMark?: number;
+  /** caps how fast output is written, unit: byte/s, unlimited by default */
+  maxBytesPerSecond?: number;
+  /**
+   * "flate2" by default, "native" uses the browser's `CompressionStream("deflate-raw")` and falls back to "flate2"
+   * where it is missing, the browser picks its own level, level 0 always uses "flate2"
+   */
+  codec?: "flate2" | "native" | "store";
+  /** with level "auto", bytes of input compressed per second the job aims at, 20 MiB by default */
+  targetSpeed?: number;
+  /** Zopfli iterations per block at level 10, from 1 to 1000, 15 by default */
+  ultraIterations?: number;
+  /** picks codec and level per entry, the first matching rule is used */
+  rules?: CompressRule[];
+}
+
+/** Every condition given must hold, a rule without condition matches every file. */
+export interface CompressRule {
+  /** matched against the entry name, like `ScanOptions` patterns */
+  glob?: string;
+  /** case-insensitive, with or without the dot */
+  extensions?: string | string[];
+  /** unit: byte, inclusive */
+  minSize?: number;
+  /** unit: byte, inclusive */
+  maxSize?: number;
+  /** a pattern matched against the type sniffed from the first bytes, e.g. "image/*" or "text/plain" */
+  mime?: string;
+  /** the codec of the job by default, "store" copies the file as it is */
+  codec?: "flate2" | "native" | "store";
+  /** the level of the job by default */
+  level?: number;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+  #[wasm_bindgen(typescript_type = "CompressOptions")]
+  pub type CompressOptionsObject;
+}
+
+const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+
+/// Every field of `CompressOptions`, anything else is rejected.
+const KNOWN_OPTIONS: &[&str] = &["fileName", "level", "signal", "speedReportInterval", "memoryBudget", "readAhead", "highWaterMark", "maxBytesPerSecond", "codec", "targetSpeed", "ultraIterations", "rules"];
+
+pub struct CompressOptions {
+  pub output_file_name: String,
+  pub compression_level: u32,
+  /// NOT IMPLEMENTED, only set by `compress_transform`
+  pub transform_script: Option<String>,
+  pub signal: Option<web_sys::AbortSignal>,
+  /// unit: ms, `None` keeps the interval of `Handles`
+  pub speed_report_interval: Option<f64>,
+  pub memory_budget: MemoryBudget,
+  /// number of files looked up ahead
+  pub read_ahead: usize,
+  /// unit: byte, `None` is the write threshold of the memory budget
+  pub high_water_mark: Option<usize>,
+  /// unit: byte/s, `None` is unlimited
+  pub max_bytes_per_second: Option<f64>,
+  /// the codec asked for, the job falls back to `flate2` if it is not supported
+  pub codec: CodecKind,
+  /// with `level: "auto"` the target speed, unit: byte/s, `compression_level` is then only the level of the first files
+  pub auto_level: Option<f64>,
+  /// Zopfli iterations at `ULTRA_COMPRESSION_LEVEL`
+  pub ultra_iterations: u64,
+  pub rules: CompressRules,
+}
+
+impl CompressOptions {
+  /// Options of the positional `compress(output_file_name, compression_level, signal)`.
+  pub fn new(output_file_name: String, compression_level: u32, signal: Option<web_sys::AbortSignal>) -> Self {
+    Self {
+      output_file_name,
+      compression_level,
+      transform_script: None,
+      signal,
+      speed_report_interval: None,
+      memory_budget: MemoryBudget::default(),
+      read_ahead: DEFAULT_READ_AHEAD,
+      high_water_mark: None,
+      max_bytes_per_second: None,
+      codec: CodecKind::Flate2,
+      auto_level: None,
+      ultra_iterations: DEFAULT_ULTRA_ITERATIONS,
+      rules: CompressRules::default(),
+    }
+  }
+
+  /// Validate a `CompressOptions` object, before anything is created.
+  pub fn parse(options: &JsValue) -> Result<Self, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::parse);
+
+    let invalid = |key: &str, value: &JsValue, expected: &str| WasmError::invalid_option(LOCATION, key, &format!("{:?}", value), expected);
+
+    if !options.is_object() {
+      return Err(invalid("options", options, "an object"));
+    }
+    let options = options.unchecked_ref::<js_sys::Object>();
+
+    let keys: Vec<String> = js_sys::Object::keys(options).iter().map(|key| key.as_string().unwrap_or_default()).collect();
+    let get = |key: &str| js_sys::Reflect::get(options, &JsValue::from_str(key)).unwrap_or(JsValue::UNDEFINED);
+
+    let mut parsed = Self::validate(&keys, |key| OptionValue::from_js(&get(key)))?;
+
+    let signal = get("signal");
+    if !signal.is_undefined() {
+      match signal.dyn_into::<web_sys::AbortSignal>() {
+        Ok(signal) => parsed.signal = Some(signal),
+        Err(signal) => return Err(invalid("signal", &signal, "an AbortSignal")),
+      }
+    }
+
+    let rules = get("rules");
+    if !rules.is_undefined() {
+      parsed.rules = CompressRules::parse(&rules)?;
+    }
+
+    Ok(parsed)
+  }
+
+  /// Validate every option but `signal` and `rules`, which are left unset, `get` gives the value of a key.
+  fn validate(keys: &[String], get: impl Fn(&str) -> OptionValue) -> Result<Self, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::parse);
+
+    let invalid = |key: &str, value: &OptionValue, expected: &str| WasmError::invalid_option(LOCATION, key, &value.to_string(), expected);
+
+    for key in keys {
+      if !KNOWN_OPTIONS.contains(&key.as_str()) {
+        return Err(WasmError::unknown_option(LOCATION, key));
+      }
+    }
+
+    let file_name = get("fileName");
+    let Some(output_file_name) = file_name.as_string().filter(|file_name| !file_name.is_empty()).map(str::to_owned) else {
+      return Err(invalid("fileName", &file_name, "a non-empty string"));
+    };
+
+    let level = get("level");
+    let is_auto = level.as_string() == Some("auto");
+    let compression_level = match level.as_f64() {
+      _ if level.is_undefined() || is_auto => DEFAULT_COMPRESSION_LEVEL,
+      Some(value) if value.fract() == 0_f64 && (0_f64..=MAX_COMPRESSION_LEVEL as f64).contains(&value) => value as u32,
+      _ => return Err(invalid("level", &level, &format!("an integer from 0 to {} or \"auto\"", MAX_COMPRESSION_LEVEL))),
+    };
+
+    let interval = get("speedReportInterval");
+    let speed_report_interval = match interval.as_f64() {
+      _ if interval.is_undefined() => None,
+      Some(value) if value.is_finite() && value > 0_f64 => Some(value),
+      _ => return Err(invalid("speedReportInterval", &interval, "a positive number of ms")),
+    };
+
+    let budget = get("memoryBudget");
+    let memory_budget = match budget.as_f64() {
+      _ if budget.is_undefined() => MemoryBudget::default(),
+      Some(value) if value.fract() == 0_f64 && (MIN_MEMORY_BUDGET as f64..=MAX_MEMORY_BUDGET as f64).contains(&value) => MemoryBudget::new(value as usize),
+      _ => return Err(invalid("memoryBudget", &budget, &format!("an integer from {} to {}", MIN_MEMORY_BUDGET, MAX_MEMORY_BUDGET))),
+    };
+
+    let depth = get("readAhead");
+    let read_ahead = match depth.as_f64() {
+      _ if depth.is_undefined() => DEFAULT_READ_AHEAD,
+      Some(value) if value.fract() == 0_f64 && (0_f64..=MAX_READ_AHEAD as f64).contains(&value) => value as usize,
+      _ => return Err(invalid("readAhead", &depth, &format!("an integer from 0 to {}", MAX_READ_AHEAD))),
+    };
+
+    let mark = get("highWaterMark");
+    let high_water_mark = match mark.as_f64() {
+      _ if mark.is_undefined() => None,
+      Some(value) if value.fract() == 0_f64 && (0_f64..=MAX_MEMORY_BUDGET as f64).contains(&value) => Some(value as usize),
+      _ => return Err(invalid("highWaterMark", &mark, &format!("an integer from 0 to {}", MAX_MEMORY_BUDGET))),
+    };
+
+    let rate = get("maxBytesPerSecond");
+    let max_bytes_per_second = match rate.as_f64() {
+      _ if rate.is_undefined() => None,
+      Some(value) if value.is_finite() && value > 0_f64 => Some(value),
+      _ => return Err(invalid("maxBytesPerSecond", &rate, "a positive number of bytes")),
+    };
+
+    let name = get("codec");
+    let codec = match name.as_string() {
+      _ if name.is_undefined() => CodecKind::Flate2,
+      Some(value) => match CodecKind::from_name(value) {
+        Some(codec) => codec,
+        None => return Err(invalid("codec", &name, "\"flate2\", \"native\" or \"store\"")),
+      },
+      None => return Err(invalid("codec", &name, "\"flate2\", \"native\" or \"store\"")),
+    };
+
+    let speed = get("targetSpeed");
+    let auto_level = match speed.as_f64() {
+      _ if !is_auto && speed.is_undefined() => None,
+      _ if !is_auto => return Err(invalid("targetSpeed", &speed, "undefined unless level is \"auto\"")),
+      _ if speed.is_undefined() => Some(DEFAULT_TARGET_SPEED),
+      Some(value) if value.is_finite() && value > 0_f64 => Some(value),
+      _ => return Err(invalid("targetSpeed", &speed, "a positive number of bytes")),
+    };
+
+    let iterations = get("ultraIterations");
+    let ultra_iterations = match iterations.as_f64() {
+      _ if iterations.is_undefined() => DEFAULT_ULTRA_ITERATIONS,
+      Some(value) if value.fract() == 0_f64 && (1_f64..=MAX_ULTRA_ITERATIONS as f64).contains(&value) => value as u64,
+      _ => return Err(invalid("ultraIterations", &iterations, &format!("an integer from 1 to {}", MAX_ULTRA_ITERATIONS))),
+    };
+
+    Ok(Self {
+      output_file_name,
+      compression_level,
+      transform_script: None,
+      signal: None,
+      speed_report_interval,
+      memory_budget,
+      read_ahead,
+      high_water_mark,
+      max_bytes_per_second,
+      codec,
+      auto_level,
+      ultra_iterations,
+      rules: CompressRules::default(),
+    })
+  }
+}
+
+/// A field of the options object, as much of it as validation looks at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionValue {
+  Undefined,
+  Number(f64),
+  String(String),
+  /// anything else, in its debug form
+  Other(String),
+}
+
+impl OptionValue {
+  pub fn from_js(value: &JsValue) -> Self {
+    if value.is_undefined() {
+      Self::Undefined
+    } else if let Some(number) = value.as_f64() {
+      Self::Number(number)
+    } else if let Some(string) = value.as_string() {
+      Self::String(string)
+    } else {
+      Self::Other(format!("{:?}", value))
+    }
+  }
+
+  fn is_undefined(&self) -> bool {
+    *self == Self::Undefined
+  }
+
+  fn as_f64(&self) -> Option<f64> {
+    match self {
+      Self::Number(number) => Some(*number),
+      _ => None,
+    }
+  }
+
+  fn as_string(&self) -> Option<&str> {
+    match self {
+      Self::String(string) => Some(string),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for OptionValue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Undefined => write!(f, "undefined"),
+      Self::Number(number) => write!(f, "{}", number),
+      Self::String(string) => write!(f, "{:?}", string),
+      Self::Other(debug) => write!(f, "{}", debug),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  fn validate(fields: &[(&str, OptionValue)]) -> Result<CompressOptions, WasmError> {
+    let fields: HashMap<&str, OptionValue> = fields.iter().cloned().collect();
+    let keys: Vec<String> = fields.keys().map(|key| key.to_string()).collect();
+    CompressOptions::validate(&keys, |key| fields.get(key).cloned().unwrap_or(OptionValue::Undefined))
+  }
+
+  fn file_name() -> (&'static str, OptionValue) {
+    ("fileName", OptionValue::String("out".to_owned()))
+  }
+
+  #[test]
+  fn unknown_keys_are_rejected() {
+    let error = validate(&[file_name(), ("levle", OptionValue::Number(9_f64))]).err().unwrap();
+    assert_eq!(error.code, 0x1B);
+    assert_eq!(error.arg1, "levle");
+  }
+
+  #[test]
+  fn invalid_values_are_rejected() {
+    let invalid = [
+      ("level", OptionValue::Number(3.5)),
+      ("level", OptionValue::Number(11_f64)),
+      ("level", OptionValue::String("fast".to_owned())),
+      ("memoryBudget", OptionValue::Number(1024_f64)),
+      ("readAhead", OptionValue::Number(-1_f64)),
+      ("speedReportInterval", OptionValue::Number(0_f64)),
+      ("maxBytesPerSecond", OptionValue::Number(f64::INFINITY)),
+      ("codec", OptionValue::String("zstd".to_owned())),
+      ("targetSpeed", OptionValue::Number(1024_f64)),
+      ("ultraIterations", OptionValue::Number(0_f64)),
+    ];
+    for (key, value) in invalid {
+      let error = validate(&[file_name(), (key, value.clone())]).err().unwrap();
+      assert_eq!(error.code, 0x1C, "{} = {}", key, value);
+      assert_eq!(error.arg1, key);
+      assert_eq!(error.arg2, value.to_string());
+    }
+
+    let error = validate(&[("fileName", OptionValue::String(String::new()))]).err().unwrap();
+    assert_eq!((error.code, error.arg1.as_str(), error.arg2.as_str()), (0x1C, "fileName", "\"\""));
+    let error = validate(&[]).err().unwrap();
+    assert_eq!((error.code, error.arg1.as_str(), error.arg2.as_str()), (0x1C, "fileName", "undefined"));
+  }
+
+  #[test]
+  fn valid_values_are_kept_and_defaults_filled() {
+    let options = validate(&[file_name()]).unwrap();
+    assert_eq!(options.output_file_name, "out");
+    assert_eq!(options.compression_level, DEFAULT_COMPRESSION_LEVEL);
+    assert_eq!(options.read_ahead, DEFAULT_READ_AHEAD);
+    assert!(options.auto_level.is_none() && options.high_water_mark.is_none());
+
+    let options = validate(&[
+      file_name(),
+      ("level", OptionValue::String("auto".to_owned())),
+      ("targetSpeed", OptionValue::Number(1024_f64)),
+      ("readAhead", OptionValue::Number(0_f64)),
+      ("codec", OptionValue::String("store".to_owned())),
+    ]).unwrap();
+    assert_eq!(options.auto_level, Some(1024_f64));
+    assert_eq!(options.read_ahead, 0);
+    assert_eq!(options.codec, CodecKind::Store);
+
+    let options = validate(&[file_name(), ("level", OptionValue::String("auto".to_owned()))]).unwrap();
+    assert_eq!(options.auto_level, Some(DEFAULT_TARGET_SPEED));
+  }
+}
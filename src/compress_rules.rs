@@ -0,0 +1,291 @@
+//! The type `CompressRules` picks the codec and level of each entry, from the `rules` of `CompressOptions`.
+//!
+//! Rules are tried in order and the first one whose conditions all hold is used, an entry no rule matches keeps the
+//! codec and level of the job. The MIME type is sniffed from the first bytes of the file, not taken from its name.
+
+use crate::codec::{CodecKind, MAX_COMPRESSION_LEVEL};
+use crate::glob::Glob;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+/// number of bytes `sniff_mime` looks at
+pub const SNIFF_LENGTH: usize = 512;
+
+/// Every field of a rule, anything else is rejected.
+const KNOWN_RULE_OPTIONS: &[&str] = &["glob", "extensions", "minSize", "maxSize", "mime", "codec", "level"];
+
+/// magic numbers at the start of a file, checked in order
+const SIGNATURES: &[(&[u8], &str)] = &[
+  (b"\x89PNG\r\n\x1a\n", "image/png"),
+  (b"\xff\xd8\xff", "image/jpeg"),
+  (b"GIF87a", "image/gif"),
+  (b"GIF89a", "image/gif"),
+  (b"\x1a\x45\xdf\xa3", "video/webm"),
+  (b"ID3", "audio/mpeg"),
+  (b"OggS", "audio/ogg"),
+  (b"fLaC", "audio/flac"),
+  (b"PK\x03\x04", "application/zip"),
+  (b"\x1f\x8b", "application/gzip"),
+  (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+  (b"Rar!\x1a\x07", "application/vnd.rar"),
+  (b"\xfd7zXZ\x00", "application/x-xz"),
+  (b"BZh", "application/x-bzip2"),
+  (b"\x28\xb5\x2f\xfd", "application/zstd"),
+  (b"%PDF-", "application/pdf"),
+  (b"\x00asm", "application/wasm"),
+  (b"wOFF", "font/woff"),
+  (b"wOF2", "font/woff2"),
+];
+
+/// MIME type of a file from its first bytes, "application/octet-stream" if unknown.
+pub fn sniff_mime(head: &[u8]) -> &'static str {
+  let head = &head[..head.len().min(SNIFF_LENGTH)];
+
+  if let Some((_, mime)) = SIGNATURES.iter().find(|(signature, _)| head.starts_with(signature)) {
+    return mime;
+  }
+  if head.len() >= 12 && head.starts_with(b"RIFF") {
+    match &head[8..12] {
+      b"WEBP" => return "image/webp",
+      b"WAVE" => return "audio/wav",
+      b"AVI " => return "video/x-msvideo",
+      _ => {},
+    }
+  }
+  if head.len() >= 12 && &head[4..8] == b"ftyp" {
+    return match &head[8..12] {
+      b"avif" | b"avis" => "image/avif",
+      b"heic" | b"heix" | b"mif1" => "image/heic",
+      b"qt  " => "video/quicktime",
+      b"M4A " => "audio/mp4",
+      _ => "video/mp4",
+    };
+  }
+  // an MPEG audio frame without ID3 tag
+  if head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0 {
+    return "audio/mpeg";
+  }
+  if is_text(head) {
+    return "text/plain";
+  }
+  "application/octet-stream"
+}
+
+/// UTF-8 without NUL and with few control characters, a character cut at the end is fine.
+fn is_text(head: &[u8]) -> bool {
+  if head.is_empty() {
+    return false;
+  }
+  let valid = match std::str::from_utf8(head) {
+    Ok(_) => true,
+    Err(e) => e.error_len().is_none(),
+  };
+  let control = head.iter().filter(|byte| **byte < 0x20 && !matches!(**byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)).count();
+  valid && !head.contains(&0) && control * 100 <= head.len()
+}
+
+pub struct CompressRule {
+  glob: Option<Glob>,
+  /// lowercase, without the dot
+  extensions: Vec<String>,
+  /// unit: byte, inclusive
+  min_size: Option<u64>,
+  /// unit: byte, inclusive
+  max_size: Option<u64>,
+  mime: Option<Glob>,
+  pub codec: Option<CodecKind>,
+  pub level: Option<u32>,
+}
+
+impl CompressRule {
+  /// `name` is the entry name, `head` the first bytes of the file, only looked at by a rule with `mime`.
+  fn is_match(&self, name: &str, size: u64, head: &[u8]) -> bool {
+    if let Some(glob) = self.glob.as_ref() {
+      if !glob.is_match(name) {
+        return false;
+      }
+    }
+    if !self.extensions.is_empty() {
+      let file_name = name.rsplit('/').next().unwrap_or(name);
+      let extension = match file_name.rfind('.') {
+        Some(index) if index > 0 => file_name[index + 1..].to_lowercase(),
+        _ => String::new(),
+      };
+      if !self.extensions.contains(&extension) {
+        return false;
+      }
+    }
+    if self.min_size.is_some_and(|min_size| size < min_size) || self.max_size.is_some_and(|max_size| size > max_size) {
+      return false;
+    }
+    if let Some(mime) = self.mime.as_ref() {
+      if !mime.is_match(sniff_mime(head)) {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+#[derive(Default)]
+pub struct CompressRules {
+  rules: Vec<CompressRule>,
+}
+
+impl CompressRules {
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty()
+  }
+
+  /// Some rule looks at the first bytes of files.
+  pub fn needs_head(&self) -> bool {
+    self.rules.iter().any(|rule| rule.mime.is_some())
+  }
+
+  /// The first rule matching an entry.
+  pub fn choose(&self, name: &str, size: u64, head: &[u8]) -> Option<&CompressRule> {
+    self.rules.iter().find(|rule| rule.is_match(name, size, head))
+  }
+
+  /// Codec and level of an entry, what the first matching rule leaves out is taken from `codec` and `level` of the job.
+  pub fn codec_and_level(&self, name: &str, size: u64, head: &[u8], codec: CodecKind, level: u32) -> (CodecKind, u32) {
+    match self.choose(name, size, head) {
+      Some(rule) => (rule.codec.unwrap_or(codec), rule.level.unwrap_or(level)),
+      None => (codec, level),
+    }
+  }
+
+  /// Validate the `rules` array of `CompressOptions`.
+  pub fn parse(value: &JsValue) -> Result<Self, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::parse);
+
+    let invalid = |key: &str, value: &JsValue, expected: &str| WasmError::invalid_option(LOCATION, key, &format!("{:?}", value), expected);
+
+    let Some(array) = value.dyn_ref::<js_sys::Array>() else {
+      return Err(invalid("rules", value, "an array"));
+    };
+
+    let mut rules = Vec::with_capacity(array.length() as usize);
+    for (index, rule) in array.iter().enumerate() {
+      let key = |field: &str| format!("rules[{}].{}", index, field);
+
+      if !rule.is_object() {
+        return Err(invalid(&format!("rules[{}]", index), &rule, "an object"));
+      }
+      let rule = rule.unchecked_ref::<js_sys::Object>();
+      for field in js_sys::Object::keys(rule).iter() {
+        let field = field.as_string().unwrap_or_default();
+        if !KNOWN_RULE_OPTIONS.contains(&field.as_str()) {
+          return Err(WasmError::unknown_option(LOCATION, &key(&field)));
+        }
+      }
+
+      let get = |field: &str| js_sys::Reflect::get(rule, &JsValue::from_str(field)).unwrap_or(JsValue::UNDEFINED);
+
+      let pattern = |field: &str| -> Result<Option<Glob>, WasmError> {
+        let value = get(field);
+        if value.is_undefined() {
+          return Ok(None);
+        }
+        match value.as_string().map(|pattern| Glob::new(&pattern)) {
+          Some(Ok(glob)) => Ok(Some(glob)),
+          Some(Err(reason)) => Err(invalid(&key(field), &value, &format!("a valid pattern, {}", reason))),
+          None => Err(invalid(&key(field), &value, "a string")),
+        }
+      };
+      let glob = pattern("glob")?;
+      let mime = pattern("mime")?;
+
+      let value = get("extensions");
+      let extensions = if value.is_undefined() {
+        Vec::new()
+      } else if let Some(extension) = value.as_string() {
+        vec![extension]
+      } else {
+        match value.dyn_ref::<js_sys::Array>().map(|array| array.iter().map(|item| item.as_string()).collect::<Option<Vec<String>>>()) {
+          Some(Some(extensions)) => extensions,
+          _ => return Err(invalid(&key("extensions"), &value, "a string or an array of strings")),
+        }
+      };
+      let extensions = extensions.iter().map(|extension| extension.trim_start_matches('.').to_lowercase()).collect();
+
+      let size = |field: &str| -> Result<Option<u64>, WasmError> {
+        let value = get(field);
+        match value.as_f64() {
+          _ if value.is_undefined() => Ok(None),
+          Some(size) if size.fract() == 0_f64 && size >= 0_f64 => Ok(Some(size as u64)),
+          _ => Err(invalid(&key(field), &value, "a non-negative integer of bytes")),
+        }
+      };
+      let min_size = size("minSize")?;
+      let max_size = size("maxSize")?;
+
+      let value = get("codec");
+      let codec = match value.as_string() {
+        _ if value.is_undefined() => None,
+        Some(name) if CodecKind::from_name(&name).is_some() => CodecKind::from_name(&name),
+        _ => return Err(invalid(&key("codec"), &value, "\"flate2\", \"native\" or \"store\"")),
+      };
+
+      let value = get("level");
+      let level = match value.as_f64() {
+        _ if value.is_undefined() => None,
+        Some(level) if level.fract() == 0_f64 && (0_f64..=MAX_COMPRESSION_LEVEL as f64).contains(&level) => Some(level as u32),
+        _ => return Err(invalid(&key("level"), &value, &format!("an integer from 0 to {}", MAX_COMPRESSION_LEVEL))),
+      };
+
+      rules.push(CompressRule { glob, extensions, min_size, max_size, mime, codec, level });
+    }
+    Ok(Self { rules })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rule(glob: Option<&str>, extensions: &[&str], size: (Option<u64>, Option<u64>), mime: Option<&str>, level: u32) -> CompressRule {
+    CompressRule {
+      glob: glob.map(|glob| Glob::new(glob).unwrap()),
+      extensions: extensions.iter().map(|extension| extension.to_string()).collect(),
+      min_size: size.0,
+      max_size: size.1,
+      mime: mime.map(|mime| Glob::new(mime).unwrap()),
+      codec: None,
+      level: Some(level),
+    }
+  }
+
+  #[test]
+  fn signatures_and_text_are_sniffed() {
+    assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+    assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+    assert_eq!(sniff_mime(b"\0\0\0\x20ftypisom\0\0\x02\0"), "video/mp4");
+    assert_eq!(sniff_mime(b"\xff\xfb\x90\x64"), "audio/mpeg");
+    assert_eq!(sniff_mime("2024-01-01 12:00:00 INFO démarrage\n".as_bytes()), "text/plain");
+    // a multi-byte character cut by the sniff length
+    assert_eq!(sniff_mime(&"é".repeat(300).as_bytes()[..SNIFF_LENGTH - 1]), "text/plain");
+    assert_eq!(sniff_mime(b"\x7fELF\x02\x01\x01\0\0\0"), "application/octet-stream");
+    assert_eq!(sniff_mime(b""), "application/octet-stream");
+  }
+
+  #[test]
+  fn first_matching_rule_wins() {
+    let rules = CompressRules {
+      rules: vec![
+        rule(None, &[], (None, None), Some("image/*"), 0),
+        rule(None, &["log", "txt"], (None, None), None, 9),
+        rule(Some("bin/**"), &[], (Some(1024), None), None, 1),
+      ],
+    };
+    assert!(rules.needs_head());
+    let level = |name: &str, size: u64, head: &[u8]| rules.choose(name, size, head).and_then(|rule| rule.level);
+
+    assert_eq!(level("photos/cat.txt", 10, b"\xff\xd8\xff\xe0"), Some(0));
+    assert_eq!(level("logs/app.LOG", 10, b"started"), Some(9));
+    assert_eq!(level("bin/tool", 4096, b"\x7fELF"), Some(1));
+    assert_eq!(level("bin/tiny", 100, b"\x7fELF"), None);
+    assert_eq!(level(".log", 10, b"no extension"), None);
+  }
+}
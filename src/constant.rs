@@ -15,3 +15,6 @@ pub const COMPRESSION_METHOD           : u16 = 8_u16;
 
 /// "store" for folders
 pub const COMPRESSION_METHOD_DIR       : u16 = 0_u16;
+
+/// "store" for files copied as they are
+pub const COMPRESSION_METHOD_STORE     : u16 = 0_u16;
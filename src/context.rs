@@ -1,13 +1,17 @@
 //! The type `Context` works like a global object, data is shared between "scan" stage and "compress" stage.
 
-use std::{cell::RefCell, mem, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
-use crate::prelude::*;
+use crate::buffer_pool::BufferPool;
+use crate::{js_futures, prelude::*};
 use crate::handles::Handles;
+use crate::wasm_error::WasmError;
 
 pub struct Context {
   pub performance            : Rc<web_sys::Performance>,
   pub scan_stage             : Rc<ContextScanStage>,
+  /// idle buffers shared by all compress jobs
+  pub buffer_pool            : Rc<BufferPool>,
 }
 
 pub struct ContextScanStage {
@@ -15,30 +19,62 @@ pub struct ContextScanStage {
   pub file_path_list         : Rc<RefCell<Vec<FilePath>>>,
 }
 
+#[derive(Clone)]
 pub struct FilePath {
-  pub path   : String,
-  pub is_dir : bool,
+  pub path          : String,
+  pub is_dir        : bool,
+  /// unit: byte, `None` if not collected during scan
+  pub size          : Option<u64>,
+  /// unit: ms since unix epoch, `None` if not collected during scan
+  pub last_modified : Option<f64>,
+  /// path inside the zip file, without leading or trailing slash
+  pub archive_path  : String,
+  /// given by `Handles::scan_files`, `None` if the file is looked up in the scanned `FileSystem`
+  pub file          : Option<web_sys::File>,
+}
+
+impl FilePath {
+  /// Get the `File` of this entry, `file_system` is only needed for entries found by `Handles::scan`.
+  pub async fn open(&self, file_system: Option<&web_sys::FileSystem>, location: &str) -> Result<web_sys::File, WasmError> {
+    if let Some(file) = self.file.as_ref() {
+      return Ok(file.clone());
+    }
+    let Some(file_system) = file_system else {
+      return Err(WasmError::missing_file_system(location));
+    };
+
+    // get FileSystemFileEntry
+    let file_entry = match js_futures::FileSystemDirectoryEntry_getFile_future::from(file_system.root(), &self.path).await {
+      Ok(file_entry) => file_entry,
+      Err(e) => return Err(WasmError::fail_to_get_file_entry(location, &format!("{:?}", e))),
+    };
+
+    // get File
+    match js_futures::FileSystemFileEntry_file_future::from(file_entry).await {
+      Ok(file) => Ok(file),
+      Err(e) => Err(WasmError::fail_to_get_file(location, &format!("{:?}", e))),
+    }
+  }
 }
 
 impl Context {
   pub fn init(create_writer: js_sys::Function) -> crate::Handles {
-    let window = web_sys::window().unwrap();
+    // `performance` exists on both `Window` and `WorkerGlobalScope`
+    let performance = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance")).unwrap();
 
     let context = Rc::new(
       Self {
-        performance: Rc::new(window.performance().unwrap()),
+        performance: Rc::new(performance.unchecked_into::<web_sys::Performance>()),
 
         scan_stage: Rc::new(ContextScanStage {
           file_system            : Rc::new(RefCell::new(None)),
           file_path_list         : Rc::new(RefCell::new(Vec::new())),
         }),
+
+        buffer_pool: Rc::new(BufferPool::default()),
       }
     );
 
-    let handles = Handles::new(&context, create_writer);
-
-    mem::forget(context);
-
-    handles
+    Handles::new(&context, create_writer)
   }
 }
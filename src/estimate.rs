@@ -0,0 +1,230 @@
+//! The type `Estimate` is what `Handles::estimate` expects of a job: its output size and duration, with a likely range.
+//!
+//! Parts of files spread over the plan are read and compressed with the settings of the job. Files are picked by evenly
+//! spaced offsets in the total bytes, so a file is looked at about as often as its size weighs in the output, and the
+//! mean ratio and time per byte of the samples are extrapolated to the whole plan. The range is two standard errors.
+
+use crate::appnote63::FileHeader;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+/// bytes read from files by one estimate, at most
+pub const MAX_SAMPLE_BYTES: u64 = 16 * 1024 * 1024;
+pub const MAX_SAMPLED_FILES: usize = 32;
+/// a sampled file is read up to this many bytes even if the sample budget is shared by many files
+pub const MIN_SAMPLE_PER_FILE: u64 = 64 * 1024;
+
+/// data descriptor after each file
+const DATA_DESCRIPTOR_SIZE: u64 = 4 + 4 + 8 + 8;
+/// zip64 end of central directory record, its locator and the end of central directory record
+const END_RECORDS_SIZE: u64 = 56 + 20 + 22;
+/// samples are small parts of files read once, the range is at least this share of the estimate
+const MIN_RELATIVE_ERROR: f64 = 0.05;
+/// with a single sample nothing tells how files differ
+const SINGLE_SAMPLE_RELATIVE_ERROR: f64 = 0.5;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Estimate {
+  /// size of the zip file, headers included, unit: byte
+  pub output_bytes: f64,
+  pub min_output_bytes: f64,
+  pub max_output_bytes: f64,
+  /// compressing on the calling thread, unit: ms
+  pub duration: f64,
+  pub min_duration: f64,
+  pub max_duration: f64,
+  /// read from files to estimate, unit: byte
+  pub sampled_bytes: f64,
+  pub sampled_files: u32,
+}
+
+/// Files to sample among files of `sizes`: the one holding each of `count` evenly spaced byte offsets, each at most once.
+pub fn pick_files(sizes: &[u64], count: usize) -> Vec<usize> {
+  let total: u64 = sizes.iter().sum();
+  if total == 0 || count == 0 {
+    return Vec::new();
+  }
+
+  let mut picked = Vec::with_capacity(count);
+  let mut index = 0;
+  let mut end_of_file = sizes[0];
+  for slot in 0..count as u64 {
+    // the middle of each of `count` equal spans
+    let offset = (total as u128 * (2 * slot as u128 + 1) / (2 * count as u128)) as u64;
+    while end_of_file <= offset {
+      index += 1;
+      end_of_file += sizes[index];
+    }
+    if picked.last() != Some(&index) {
+      picked.push(index);
+    }
+  }
+  picked
+}
+
+/// Offset and length of the part read from a file of `size`, from its middle as the start is often a header.
+pub fn sample_window(size: u64, length: u64) -> (u64, u64) {
+  let length = length.min(size);
+  ((size - length) / 2, length)
+}
+
+/// Bytes written around file data: every header, data descriptor, and the records closing the zip file.
+///
+/// `entries` are the entry names as written, directories end with a slash.
+pub fn header_bytes<'a>(entries: impl Iterator<Item = &'a str>) -> u64 {
+  let mut buffer = Vec::with_capacity(1024);
+  let mut total = END_RECORDS_SIZE;
+  for name in entries {
+    let is_dir = name.ends_with('/');
+    let file_header = FileHeader::new(name.to_owned(), 0, is_dir);
+    file_header.write_into_as_lfh(&mut buffer);
+    file_header.write_into_as_cdh(&mut buffer);
+    total += buffer.len() as u64;
+    if !is_dir {
+      total += DATA_DESCRIPTOR_SIZE;
+    }
+  }
+  total
+}
+
+/// Read `length` bytes of `file` from `offset`.
+pub async fn read_sample(file: &web_sys::File, offset: u64, length: u64) -> Result<Vec<u8>, WasmError> {
+  #[allow(non_snake_case)]
+  let LOCATION = utils::type_name(&read_sample);
+
+  let blob = match file.slice_with_f64_and_f64(offset as f64, (offset + length) as f64) {
+    Ok(blob) => blob,
+    Err(e) => return Err(WasmError::fail_to_read(LOCATION, &format!("{:?}", e))),
+  };
+  match utils::await_promise(blob.array_buffer()).await {
+    Ok(array_buffer) => Ok(js_sys::Uint8Array::new(&array_buffer).to_vec()),
+    Err(e) => Err(WasmError::fail_to_read(LOCATION, &format!("{:?}", e))),
+  }
+}
+
+/// One sampled part of a file.
+struct Sample {
+  /// unit: byte
+  input: u64,
+  /// unit: byte
+  output: u64,
+  /// looking up and opening the file, unit: ms
+  open_time: f64,
+  /// reading and compressing, unit: ms
+  time: f64,
+}
+
+/// Mean and standard error of the mean.
+fn mean_and_error(values: impl Iterator<Item = f64> + Clone) -> (f64, f64) {
+  let count = values.clone().count() as f64;
+  let mean = values.clone().sum::<f64>() / count;
+  if count < 2_f64 {
+    return (mean, mean * SINGLE_SAMPLE_RELATIVE_ERROR);
+  }
+  let variance = values.map(|value| (value - mean) * (value - mean)).sum::<f64>() / (count - 1_f64);
+  (mean, (variance / count).sqrt().max(mean * MIN_RELATIVE_ERROR / 2_f64))
+}
+
+#[derive(Default)]
+pub struct Estimator {
+  samples: Vec<Sample>,
+}
+
+impl Estimator {
+  pub fn push(&mut self, input: u64, output: u64, open_time: f64, time: f64) {
+    if input > 0 {
+      self.samples.push(Sample { input, output, open_time, time });
+    }
+  }
+
+  /// `data_bytes` is the size of `file_count` files, `header_bytes` what `header_bytes` returns for every entry.
+  pub fn finish(&self, file_count: usize, data_bytes: u64, header_bytes: u64, max_bytes_per_second: Option<f64>) -> Estimate {
+    let sampled_bytes = self.samples.iter().map(|sample| sample.input).sum::<u64>() as f64;
+    let headers = header_bytes as f64;
+    let data = data_bytes as f64;
+    let files = file_count as f64;
+
+    if self.samples.is_empty() {
+      return Estimate {
+        output_bytes: headers + data,
+        min_output_bytes: headers + data,
+        max_output_bytes: headers + data,
+        duration: 0_f64,
+        min_duration: 0_f64,
+        max_duration: 0_f64,
+        sampled_bytes,
+        sampled_files: 0,
+      };
+    }
+
+    let (ratio, ratio_error) = mean_and_error(self.samples.iter().map(|sample| sample.output as f64 / sample.input as f64));
+    let (open_time, open_error) = mean_and_error(self.samples.iter().map(|sample| sample.open_time));
+    let (byte_time, byte_error) = mean_and_error(self.samples.iter().map(|sample| sample.time / sample.input as f64));
+
+    // deflate barely grows incompressible data, stored data never changes
+    let max_ratio = ratio.max(1_f64);
+    let output = |ratio: f64| headers + data * ratio.clamp(0_f64, max_ratio);
+    // the output can not be sent faster than `max_bytes_per_second`
+    let duration = |open_time: f64, byte_time: f64, output: f64| {
+      let duration = files * open_time.max(0_f64) + data * byte_time.max(0_f64);
+      match max_bytes_per_second {
+        Some(rate) => duration.max(output / rate * 1000_f64),
+        None => duration,
+      }
+    };
+
+    let output_bytes = output(ratio);
+    let min_output_bytes = output(ratio - 2_f64 * ratio_error);
+    let max_output_bytes = output(ratio + 2_f64 * ratio_error);
+    Estimate {
+      output_bytes,
+      min_output_bytes,
+      max_output_bytes,
+      duration: duration(open_time, byte_time, output_bytes),
+      min_duration: duration(open_time - 2_f64 * open_error, byte_time - 2_f64 * byte_error, min_output_bytes),
+      max_duration: duration(open_time + 2_f64 * open_error, byte_time + 2_f64 * byte_error, max_output_bytes),
+      sampled_bytes,
+      sampled_files: self.samples.len() as u32,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn large_files_are_picked_more_often_but_once() {
+    assert_eq!(pick_files(&[100, 100, 100, 100], 4), vec![0, 1, 2, 3]);
+    assert_eq!(pick_files(&[100, 100, 100, 100], 2), vec![1, 3]);
+    assert_eq!(pick_files(&[10, 1000, 0, 10], 8), vec![1]);
+    assert_eq!(pick_files(&[0, 0], 8), Vec::<usize>::new());
+    assert_eq!(sample_window(1000, 100), (450, 100));
+    assert_eq!(sample_window(50, 100), (0, 50));
+  }
+
+  #[test]
+  fn samples_are_extrapolated_with_a_range() {
+    let mut estimator = Estimator::default();
+    estimator.push(1000, 400, 2_f64, 10_f64);
+    estimator.push(1000, 600, 2_f64, 30_f64);
+    let estimate = estimator.finish(10, 100_000, 1000, None);
+    assert_eq!(estimate.output_bytes, 51_000_f64);
+    assert!(estimate.min_output_bytes < 51_000_f64 && estimate.min_output_bytes >= 1000_f64);
+    assert!(estimate.max_output_bytes > 51_000_f64 && estimate.max_output_bytes <= 101_000_f64);
+    assert_eq!(estimate.duration, 10_f64 * 2_f64 + 100_000_f64 * 0.02);
+    assert!(estimate.min_duration < estimate.duration && estimate.duration < estimate.max_duration);
+    assert_eq!(estimate.sampled_files, 2);
+
+    // sending is slower than compressing
+    let estimate = estimator.finish(10, 100_000, 1000, Some(1000_f64));
+    assert_eq!(estimate.duration, 51_000_f64);
+  }
+
+  #[test]
+  fn headers_match_the_written_records() {
+    // local file header (30 + name + 20 of zip64 extra field), central directory header (46 + name + 32 of zip64 extra field)
+    assert_eq!(header_bytes(std::iter::once("a.txt")), END_RECORDS_SIZE + 30 + 5 + 20 + 46 + 5 + 32 + DATA_DESCRIPTOR_SIZE);
+  }
+}
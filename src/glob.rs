@@ -0,0 +1,262 @@
+//! A tiny glob matcher for slash separated relative paths.
+//!
+//! Supported syntax:
+//!
+//! * `?`      - any single character except '/'
+//! * `*`      - any sequence of characters except '/'
+//! * `**`     - any sequence of characters including '/', `**/` also matches zero directories
+//! * `[abc]`, `[a-z]`, `[!a-z]` - character classes, never match '/'
+//! * `\x`     - the literal character `x`
+//!
+//! A pattern without any '/' is matched against the last component of a path, so `*.log` matches
+//! `a/b/c.log`. A leading '/' anchors the pattern and is otherwise ignored.
+
+#[derive(Debug, Clone)]
+enum Token {
+  Literal(char),
+  AnyChar,
+  AnySequence,
+  AnyPath,
+  /// `**/`, zero or more complete directories
+  AnyDirectories,
+  Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Glob {
+  tokens: Vec<Token>,
+  basename_only: bool,
+}
+
+impl Glob {
+  /// Compile a pattern, the error is a human readable reason.
+  pub fn new(pattern: &str) -> Result<Self, String> {
+    if pattern.is_empty() {
+      return Err("empty pattern".to_owned());
+    }
+
+    let basename_only = !pattern.trim_end_matches('/').contains('/');
+    let body = pattern.strip_prefix('/').unwrap_or(pattern);
+
+    let chars: Vec<char> = body.chars().collect();
+    let mut tokens = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+      match chars[i] {
+        '?' => tokens.push(Token::AnyChar),
+        '*' => {
+          if chars.get(i + 1) == Some(&'*') {
+            let at_segment_start = i == 0 || chars[i - 1] == '/';
+            if at_segment_start && chars.get(i + 2) == Some(&'/') {
+              tokens.push(Token::AnyDirectories);
+              i += 2;
+            } else {
+              tokens.push(Token::AnyPath);
+              i += 1;
+              // collapse runs like `***`
+              while chars.get(i + 1) == Some(&'*') {
+                i += 1;
+              }
+            }
+          } else {
+            tokens.push(Token::AnySequence);
+          }
+        },
+        '[' => {
+          let mut j = i + 1;
+          let negated = matches!(chars.get(j), Some('!') | Some('^'));
+          if negated {
+            j += 1;
+          }
+          let mut ranges = Vec::new();
+          let mut closed = false;
+          let mut first = true;
+          while j < chars.len() {
+            let c = chars[j];
+            if c == ']' && !first {
+              closed = true;
+              break;
+            }
+            first = false;
+            let c = if c == '\\' && j + 1 < chars.len() { j += 1; chars[j] } else { c };
+            if chars.get(j + 1) == Some(&'-') && j + 2 < chars.len() && chars[j + 2] != ']' {
+              let end = chars[j + 2];
+              if end < c {
+                return Err(format!("invalid range '{}-{}'", c, end));
+              }
+              ranges.push((c, end));
+              j += 3;
+            } else {
+              ranges.push((c, c));
+              j += 1;
+            }
+          }
+          if !closed {
+            return Err("unclosed character class".to_owned());
+          }
+          tokens.push(Token::Class { negated, ranges });
+          i = j;
+        },
+        '\\' => {
+          match chars.get(i + 1) {
+            Some(c) => tokens.push(Token::Literal(*c)),
+            None => return Err("trailing escape character".to_owned()),
+          }
+          i += 1;
+        },
+        c => tokens.push(Token::Literal(c)),
+      }
+      i += 1;
+    }
+
+    Ok(Self { tokens, basename_only })
+  }
+
+  /// `path` MUST be relative and use '/' as separator.
+  pub fn is_match(&self, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let target = if self.basename_only {
+      let trimmed = path.trim_end_matches('/');
+      match trimmed.rfind('/') {
+        Some(index) => &path[index + 1..],
+        None => path,
+      }
+    } else {
+      path
+    };
+    let text: Vec<char> = target.chars().collect();
+    match_tokens(&self.tokens, &text)
+  }
+
+  /// Directories are also tested with a trailing slash, so that `foo/**` prunes `foo` itself.
+  pub fn is_match_entry(&self, path: &str, is_dir: bool) -> bool {
+    if self.is_match(path) {
+      return true;
+    }
+    is_dir && self.is_match(&format!("{}/", path.trim_end_matches('/')))
+  }
+}
+
+/// Tokens which may match an empty text.
+fn matches_empty(token: &Token) -> bool {
+  matches!(token, Token::AnySequence | Token::AnyPath | Token::AnyDirectories)
+}
+
+/// Follow tokens matching an empty text, `states` is filled in token order so chains of them are followed too.
+fn close(tokens: &[Token], states: &mut [bool]) {
+  for (i, token) in tokens.iter().enumerate() {
+    if states[i] && matches_empty(token) {
+      states[i + 1] = true;
+    }
+  }
+}
+
+/// Run the tokens over the text one character at a time, keeping every token the text read so far may be up to,
+/// so no pattern takes more than `tokens.len()` steps per character.
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+  // `states[i]`: the first `i` tokens match the text read so far
+  let mut states = vec![false; tokens.len() + 1];
+  // `skipping[i]`: the `AnyDirectories` token `i` is in the middle of a skipped directory
+  let mut skipping = vec![false; tokens.len()];
+  states[0] = true;
+  close(tokens, &mut states);
+
+  for c in text {
+    let mut next_states = vec![false; tokens.len() + 1];
+    let mut next_skipping = vec![false; tokens.len()];
+    for (i, token) in tokens.iter().enumerate() {
+      if skipping[i] {
+        if *c == '/' {
+          next_states[i] = true;
+        } else {
+          next_skipping[i] = true;
+        }
+      }
+      if !states[i] {
+        continue;
+      }
+      match token {
+        Token::Literal(literal) => next_states[i + 1] |= literal == c,
+        Token::AnyChar => next_states[i + 1] |= *c != '/',
+        Token::Class { negated, ranges } => {
+          let in_class = ranges.iter().any(|(start, end)| start <= c && c <= end);
+          next_states[i + 1] |= *c != '/' && in_class != *negated;
+        },
+        Token::AnySequence => next_states[i] |= *c != '/',
+        Token::AnyPath => next_states[i] = true,
+        Token::AnyDirectories => {
+          if *c == '/' {
+            next_states[i] = true;
+          } else {
+            next_skipping[i] = true;
+          }
+        },
+      }
+    }
+    close(tokens, &mut next_states);
+    if !next_states.iter().chain(next_skipping.iter()).any(|state| *state) {
+      return false;
+    }
+    states = next_states;
+    skipping = next_skipping;
+  }
+
+  states[tokens.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn is_match(pattern: &str, path: &str) -> bool {
+    Glob::new(pattern).unwrap().is_match(path)
+  }
+
+  #[test]
+  fn wildcards_respect_separators() {
+    assert!(is_match("src/*.rs", "src/lib.rs"));
+    assert!(!is_match("src/*.rs", "src/a/lib.rs"));
+    assert!(is_match("src/**", "src/a/b/lib.rs"));
+    assert!(is_match("src/**/*.rs", "src/lib.rs"));
+    assert!(is_match("src/**/*.rs", "src/a/b/lib.rs"));
+    assert!(!is_match("src/**/*.rs", "srca/lib.rs"));
+    assert!(is_match("a/**b", "a/x/yb"));
+    assert!(is_match("a/?.txt", "a/b.txt"));
+    assert!(!is_match("a?b", "a/b"));
+    assert!(!is_match("a/?.txt", "a/bc.txt"));
+  }
+
+  #[test]
+  fn classes_and_escapes() {
+    assert!(is_match("file[0-9].txt", "file7.txt"));
+    assert!(!is_match("file[0-9].txt", "filex.txt"));
+    assert!(is_match("file[!0-9].txt", "filex.txt"));
+    assert!(!is_match("a[/]b", "a/b"));
+    assert!(is_match("[]]", "]"));
+    assert!(is_match("\\*.txt", "*.txt"));
+    assert!(!is_match("\\*.txt", "a.txt"));
+    assert!(Glob::new("[a-").is_err());
+    assert!(Glob::new("[z-a]").is_err());
+    assert!(Glob::new("a\\").is_err());
+    assert!(Glob::new("").is_err());
+  }
+
+  #[test]
+  fn anchoring_and_basenames() {
+    assert!(is_match("*.log", "a/b/c.log"));
+    assert!(is_match("build/", "x/build/"));
+    assert!(is_match("/a/*.log", "a/c.log"));
+    assert!(!is_match("/a/*.log", "x/a/c.log"));
+    assert!(!is_match("a/*.log", "x/a/c.log"));
+    assert!(Glob::new("foo/**").unwrap().is_match_entry("foo", true));
+    assert!(!Glob::new("foo/**").unwrap().is_match_entry("foo", false));
+  }
+
+  #[test]
+  fn backtracking_patterns_stay_linear() {
+    let path = "x/".to_owned() + &"a/".repeat(2000) + "c";
+    assert!(!is_match("x/**a**a**a**a**a**b", &path));
+    assert!(is_match("x/**a**a**a**a**a**c", &path));
+    assert!(!is_match("*a*a*a*a*a*a*a*b", &"a".repeat(5000)));
+  }
+}
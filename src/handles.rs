@@ -1,18 +1,58 @@
 //! The type `Handles` is the main entrance where users operate on this library.
 
 use std::io::Write;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::rc::Rc;
 
-use crc32fast::Hasher;
-use flate2::{write::DeflateEncoder, Compression};
-
-use crate::constant::{VERSION_MADE_BY, VERSION_NEEDED_TO_EXTRACT};
 use crate::recover_writer::RecoverWriter;
+use crate::output_limits::OutputLimits;
 use crate::transform_writer::TransformWriter;
 use crate::utils::ReadResult;
-use crate::{appnote63, js_futures, prelude::*, utils};
+use crate::{js_futures, plan, prelude::*, utils};
+use crate::compress_options::{CompressOptions, CompressOptionsObject};
 use crate::context::{Context, FilePath};
+use crate::estimate::Estimate;
+use crate::job::{ArchiveJob, JobSnapshot, JobSummary};
+use crate::ignore_rules::{IgnoreFile, IgnoreMatch, IgnoreStack, IGNORE_FILE_NAMES};
+use crate::layout::LayoutOptions;
+use crate::operation_state::{Operation, OperationGuard, OperationState};
+use crate::pause_gate::PauseGate;
+use crate::plan::{PlanEntry, PlanTotals};
+use crate::scan_options::ScanOptions;
+use crate::scan_report::{ScanProblem, ScanReport};
+use crate::semaphore::Semaphore;
 use crate::wasm_error::WasmError;
+#[cfg(feature = "worker-pool")]
+use crate::worker_pool::WorkerPool;
+
+/// Shared by all concurrent tasks of one scan.
+struct ScanState<'a> {
+  options: &'a ScanOptions,
+  /// bounds the number of directory reads and file lookups in flight
+  semaphore: Semaphore,
+  directories_found: Cell<usize>,
+  files_found: Cell<usize>,
+  problems: RefCell<Vec<ScanProblem>>,
+}
+
+impl ScanState<'_> {
+  /// In tolerant mode record the failure and return `Ok`, otherwise return `error`.
+  fn tolerate(&self, path: &str, upstream_error: &JsValue, error: WasmError) -> Result<(), WasmError> {
+    if !self.options.tolerant() {
+      return Err(error);
+    }
+    self.problems.borrow_mut().push(ScanProblem::new(path, upstream_error));
+    Ok(())
+  }
+}
+
+/// What the "scan_filter" callback decided for an entry.
+enum ScanFilterResult {
+  Skip,
+  Keep,
+  Remap(String),
+}
 
 #[wasm_bindgen]
 pub struct Handles {
@@ -23,11 +63,26 @@ pub struct Handles {
 
   create_writer: js_sys::Function,
 
-  scan_progress: Option<js_sys::Function>,
-  compress_progress: Option<js_sys::Function>,
-  average_speed: Option<js_sys::Function>,
-  current_speed: Option<js_sys::Function>,
-  current_file: Option<js_sys::Function>,
+  layout: RefCell<LayoutOptions>,
+
+  pause_gate: PauseGate,
+
+  operation_state: OperationState,
+
+  #[cfg(feature = "worker-pool")]
+  worker_pool: RefCell<Option<Rc<WorkerPool>>>,
+
+  scan_progress: RefCell<Option<js_sys::Function>>,
+  scan_discovery: RefCell<Option<js_sys::Function>>,
+  scan_ignored: RefCell<Option<js_sys::Function>>,
+  scan_filter: RefCell<Option<js_sys::Function>>,
+  compress_progress: RefCell<Option<js_sys::Function>>,
+  byte_progress: RefCell<Option<js_sys::Function>>,
+  eta: RefCell<Option<js_sys::Function>>,
+  average_speed: RefCell<Option<js_sys::Function>>,
+  current_speed: RefCell<Option<js_sys::Function>>,
+  current_file: RefCell<Option<js_sys::Function>>,
+  state_change: RefCell<Option<js_sys::Function>>,
 }
 
 impl Handles {
@@ -36,387 +91,235 @@ impl Handles {
       context: Rc::clone(context),
       speed_report_interval: 5000_f64,
       create_writer,
-      scan_progress: None,
-      compress_progress: None,
-      average_speed: None,
-      current_speed: None,
-      current_file: None,
+      layout: RefCell::new(LayoutOptions::default()),
+      pause_gate: PauseGate::new(),
+      operation_state: OperationState::default(),
+      #[cfg(feature = "worker-pool")]
+      worker_pool: RefCell::new(None),
+      scan_progress: RefCell::new(None),
+      scan_discovery: RefCell::new(None),
+      scan_ignored: RefCell::new(None),
+      scan_filter: RefCell::new(None),
+      compress_progress: RefCell::new(None),
+      byte_progress: RefCell::new(None),
+      eta: RefCell::new(None),
+      average_speed: RefCell::new(None),
+      current_speed: RefCell::new(None),
+      current_file: RefCell::new(None),
+      state_change: RefCell::new(None),
     }
   }
 
-  async fn scan_internal(&self, entries: js_sys::Array) -> Result<JsValue, WasmError> {
-    #[allow(non_snake_case, unused_variables)]
-    let LOCATION = utils::type_name(&Self::scan_internal);
-
-    let array_length = entries.length();
-    let mut temp_vector = Vec::<web_sys::FileSystemEntry>::with_capacity(array_length as usize);
-    for i in 0..array_length {
-      temp_vector.push(entries.get(i).unchecked_into::<web_sys::FileSystemEntry>());
-    }
-
-    let entries = temp_vector;
-    for entry in entries {
-      let full_path = entry.full_path();
-
-      if entry.is_file() {
-        self.context.scan_stage.file_path_list.borrow_mut().push(FilePath { path: full_path, is_dir: false });
-
-        self.report_scan_progress(self.context.scan_stage.file_path_list.borrow().len())?;
-      }
-      else if entry.is_directory() {
-        self.context.scan_stage.file_path_list.borrow_mut().push(FilePath { path: full_path, is_dir: true });
-
-        self.report_scan_progress(self.context.scan_stage.file_path_list.borrow().len())?;
+  /// Scan sibling entries concurrently, the result keeps the order of `entries`, each directory followed by its content.
+  async fn scan_internal(&self, entries: Vec<web_sys::FileSystemEntry>, state: &ScanState<'_>, depth: u32, ignore: Option<Rc<IgnoreStack>>, archive_dir: Option<&str>) -> Result<Vec<FilePath>, WasmError> {
+    let subtrees = futures::future::try_join_all(
+      entries.into_iter().map(|entry| self.scan_entry(entry, state, depth, ignore.clone(), archive_dir))
+    ).await?;
 
-        let directory_entry = entry.unchecked_into::<web_sys::FileSystemDirectoryEntry>();
-        let reader = directory_entry.create_reader();
-
-        let mut array = js_sys::Array::new();
-        loop {
-          let cloned_reader = reader.clone();
-          let partial: js_sys::Array = js_futures::FileSystemDirectoryReader_readEntries_future::from(cloned_reader).await.unwrap();
-          if partial.length() > 0 {
-            array = array.concat(&partial);
-          } else {
-            break;
-          }
-        }
-
-        if let Err(e) = Box::pin(self.scan_internal(array)).await {
-          return Err(e);
-        }
-      }
-      else {
-        return Err(WasmError::unknown_file_entry(utils::type_name(&Handles::scan)));
-      }
-    }
-
-    Ok(JsValue::UNDEFINED)
+    Ok(subtrees.into_iter().flatten().collect())
   }
 
-  async fn compress_internal(&self, mut output_file_name: String, compression_level: u32, transform_script: Option<String>) -> Result<JsValue, WasmError> {
+  async fn scan_entry(&self, entry: web_sys::FileSystemEntry, state: &ScanState<'_>, depth: u32, ignore: Option<Rc<IgnoreStack>>, archive_dir: Option<&str>) -> Result<Vec<FilePath>, WasmError> {
     #[allow(non_snake_case)]
-    let LOCATION = utils::type_name(&Self::compress_internal);
-
-    if compression_level > 9 {
-      return Err(WasmError::invalid_compression_level(LOCATION, &format!("{}", compression_level)));
-    }
+    let LOCATION = utils::type_name(&Self::scan_entry);
 
-    if self.context.scan_stage.file_path_list.borrow().len() == 0 {
-      return Err(WasmError::empty_file_list(LOCATION));
-    }
-    let file_path_list = self.context.scan_stage.file_path_list.borrow();
+    utils::check_signal(state.options.signal(), LOCATION)?;
 
-    let file_system = self.context.scan_stage.file_system.borrow();
-    let Some(file_system) = file_system.as_ref() else {
-      return Err(WasmError::missing_file_system(LOCATION));
-    };
+    let options = state.options;
+    let full_path = entry.full_path();
 
-    if !output_file_name.ends_with(".zip") {
-      output_file_name += ".zip";
-    }
-    let writer = match self.create_writer.call1(&JsValue::NULL, &JsValue::from_str(&output_file_name)) {
-      Ok(w) => w,
-      Err(e) => return Err(WasmError::fail_to_create_writer(LOCATION, &format!("{:?}", e))),
+    // children of a remapped directory follow their parent inside the archive
+    let archive_path = match archive_dir {
+      Some(archive_dir) => format!("{}/{}", archive_dir, entry.name()),
+      None => full_path.trim_start_matches('/').to_owned(),
     };
-    let Ok(writer) = writer.dyn_into::<web_sys::WritableStreamDefaultWriter>() else {
-      return Err(WasmError::dynamic_cast_error(LOCATION, "JsValue", "WritableStreamDefaultWriter"));
-    };
-
-    let mut bytes_written = 0_u64;
-
-    let mut file_headers = Vec::with_capacity(file_path_list.len());
-
-    let mut buffer_header = Vec::<u8>::with_capacity(64 * 1024);
-    let mut buffer_read = vec![0_u8; 16 * 1024 * 1024];
-    let mut array_buffer_read = js_sys::ArrayBuffer::new(16 * 1024 * 1024);
-
-    const TRANSFORM_BUFFER_WRITE_THRESHOLD: usize = 16 * 1024 * 1024;
-    let mut transform_writer = TransformWriter::new(
-      &writer,
-      TRANSFORM_BUFFER_WRITE_THRESHOLD,
-      8,
-      16 * 1024 * 1024,
-      transform_script.is_none(),
-    );
-
-    let mut deflate_encoder = DeflateEncoder::new(Vec::<u8>::with_capacity(16 * 1024 * 1024), Compression::new(compression_level));
-    let mut crc32_hasher = Hasher::new();
-
-    self.report_compress_progress(file_headers.len(), file_headers.capacity())?;
-
-    let speed_report_start_time = self.context.performance.now();
-    let mut speed_report_last_time = speed_report_start_time;
-    let mut speed_report_current_time;
-    let mut speed_report_delta_time;
-    let mut speed_report_last_total_bytes = 0;
-    let mut speed_report_current_total_bytes;
-    let mut speed_report_delta_total_bytes;
 
-    for FilePath { path, is_dir } in file_path_list.iter() { // start of file loop
-      self.report_current_file(path)?;
-
-      // create file header
-      let mut zip_path = path.trim_start_matches('/').to_owned();
-      if *is_dir {
-        zip_path.push('/');
+    if entry.is_file() {
+      let relative_path = full_path.trim_start_matches('/');
+      if options.is_excluded(relative_path, false) || !options.is_included(relative_path, false) {
+        return Ok(Vec::new());
       }
-      let mut file_header = appnote63::FileHeader::new(zip_path, bytes_written, *is_dir);
-
-      // write local file header
-      file_header.write_into_as_lfh(&mut buffer_header);
-      transform_writer.write(&buffer_header).unwrap();
-      bytes_written += buffer_header.len() as u64;
-
-      transform_writer.dump(true).await?;
-
-      // speed measurement
-      speed_report_current_time = self.context.performance.now();
-      speed_report_current_total_bytes = bytes_written;
-      speed_report_delta_time = speed_report_current_time - speed_report_last_time;
-      speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
-      if speed_report_delta_time >= self.speed_report_interval {
-        self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
-        self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
-        speed_report_last_time = speed_report_current_time;
-        speed_report_last_total_bytes = speed_report_current_total_bytes;
+      if let Some(IgnoreMatch { source, pattern }) = IgnoreStack::check(&ignore, relative_path, false) {
+        self.report_scan_ignored(&full_path, source, pattern)?;
+        return Ok(Vec::new());
       }
 
-      if *is_dir {
-        file_headers.push(file_header);
-        continue;
+      let mut file_path = FilePath { path: full_path, is_dir: false, size: None, last_modified: None, archive_path, file: None };
+
+      if self.scan_filter.borrow().is_some() || options.collect_metadata() {
+        let file = {
+          let _permit = state.semaphore.acquire().await;
+          js_futures::FileSystemFileEntry_file_future::from(entry.unchecked_into::<web_sys::FileSystemFileEntry>()).await
+        };
+        let file = match file {
+          Ok(file) => file,
+          Err(e) => {
+            state.tolerate(&file_path.path, &e, WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e)))?;
+            return Ok(Vec::new());
+          },
+        };
+        file_path.size = Some(file.size() as u64);
+        file_path.last_modified = Some(file.last_modified());
       }
 
-      // get FileSystemFileEntry
-      let file_entry = match js_futures::FileSystemDirectoryEntry_getFile_future::from(file_system.root(), path).await {
-        Ok(value) => value,
-        Err(e) => return Err(WasmError::fail_to_get_file_entry(LOCATION, &format!("{:?}", e))),
-      };
-
-      // get File
-      let file = match js_futures::FileSystemFileEntry_file_future::from(file_entry).await {
-        Ok(file) => file,
-        Err(e) => return Err(WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e))),
-      };
-
-      // cast File to Blob
-      let Ok(blob) = file.dyn_into::<web_sys::Blob>() else {
-        return Err(WasmError::dynamic_cast_error(LOCATION, "File", "Blob"));
-      };
-
-      // get BYOB reader
-      let stream = blob.stream();
-      let get_reader_option = web_sys::ReadableStreamGetReaderOptions::new();
-      get_reader_option.set_mode(web_sys::ReadableStreamReaderMode::Byob);
-      let Ok(reader) = stream.get_reader_with_options(&get_reader_option).dyn_into::<web_sys::ReadableStreamByobReader>() else {
-        return Err(WasmError::dynamic_cast_error(LOCATION, "Object", "ReadableStreamByobReader"));
-      };
-
-      let mut uncompressed_size = 0_u64;
-      let mut compressed_size = 0_u64;
-
-      // the read-compress-write loop
-      loop { // start of compress loop
-        // js reader -> js buffer
-        let ReadResult { new_buffer: array_buffer_new_read, view: view_read, done } = utils::byob_read(&array_buffer_read, &reader).await?;
+      if self.scan_filter.borrow().is_some() {
+        match self.invoke_scan_filter(&file_path)? {
+          ScanFilterResult::Skip => return Ok(Vec::new()),
+          ScanFilterResult::Keep => {},
+          ScanFilterResult::Remap(archive_path) => file_path.archive_path = archive_path,
+        }
+      }
 
-        let bytes_read = view_read.byte_length();
-        uncompressed_size += bytes_read as u64;
+      state.files_found.set(state.files_found.get() + 1);
+      self.report_scan_discovery(state)?;
 
-        array_buffer_read = array_buffer_new_read; // replace old detached ArrayBuffer immediately
+      Ok(vec![file_path])
+    }
+    else if entry.is_directory() {
+      // excluded directories are never read
+      let relative_path = full_path.trim_start_matches('/');
+      if options.is_excluded(relative_path, true) {
+        return Ok(Vec::new());
+      }
+      if let Some(IgnoreMatch { source, pattern }) = IgnoreStack::check(&ignore, relative_path, true) {
+        self.report_scan_ignored(&full_path, source, pattern)?;
+        return Ok(Vec::new());
+      }
 
-        // if indeed read some bytes
-        if bytes_read > 0 {
-          // js buffer -> wasm buffer slice
-          let wasm_slice = &mut buffer_read[0..bytes_read as usize];
-          view_read.copy_to(wasm_slice);
+      let mut file_path = FilePath { path: full_path, is_dir: true, size: None, last_modified: None, archive_path, file: None };
 
-          // wasm buffer slice -> crc hasher
-          crc32_hasher.update(wasm_slice);
+      if self.scan_filter.borrow().is_some() {
+        match self.invoke_scan_filter(&file_path)? {
+          ScanFilterResult::Skip => return Ok(Vec::new()),
+          ScanFilterResult::Keep => {},
+          ScanFilterResult::Remap(archive_path) => file_path.archive_path = archive_path,
+        }
+      }
 
-          // wasm buffer slice -> deflate encoder -> inner buffer
-          deflate_encoder.get_mut().clear();
-          if let Err(e) = deflate_encoder.write_all(wasm_slice) {
-            return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e)));
-          }
+      state.directories_found.set(state.directories_found.get() + 1);
+      self.report_scan_discovery(state)?;
 
-          let bytes_output = deflate_encoder.get_ref().len();
+      if !options.should_descend(depth) {
+        return Ok(if options.keeps_directory(&[]) { vec![file_path] } else { Vec::new() });
+      }
 
-          // if indeed get some compressed bytes
-          if bytes_output > 0 {
-            compressed_size += bytes_output as u64;
+      let (children, child_ignore) = {
+        let _permit = state.semaphore.acquire().await;
 
-            // inner buffer -> transform writer
-            transform_writer.write(deflate_encoder.get_ref()).unwrap();
+        let directory_entry = entry.unchecked_into::<web_sys::FileSystemDirectoryEntry>();
+        let reader = directory_entry.create_reader();
 
-            transform_writer.dump(true).await?;
+        // in tolerant mode, entries read before a failure are still scanned
+        let mut children = Vec::<web_sys::FileSystemEntry>::new();
+        loop {
+          utils::check_signal(options.signal(), LOCATION)?;
 
-            // speed measurement
-            speed_report_current_time = self.context.performance.now();
-            speed_report_current_total_bytes = bytes_written + compressed_size;
-            speed_report_delta_time = speed_report_current_time - speed_report_last_time;
-            speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
-            if speed_report_delta_time >= self.speed_report_interval {
-              self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
-              self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
-              speed_report_last_time = speed_report_current_time;
-              speed_report_last_total_bytes = speed_report_current_total_bytes;
-            }
+          let cloned_reader = reader.clone();
+          let partial = match js_futures::FileSystemDirectoryReader_readEntries_future::from(cloned_reader).await {
+            Ok(partial) => partial,
+            Err(e) => {
+              state.tolerate(&file_path.path, &e, WasmError::fail_to_read_directory(LOCATION, &file_path.path, &format!("{:?}", e)))?;
+              break;
+            },
+          };
+          if partial.length() > 0 {
+            children.extend(partial.iter().map(|child| child.unchecked_into::<web_sys::FileSystemEntry>()));
+          } else {
+            break;
           }
         }
 
-        if done {
-          // deflate encoder -> inner buffer
-          deflate_encoder.get_mut().clear();
-          let inner_buffer = match deflate_encoder.finish() {
-            Ok(inner_buffer) => inner_buffer,
-            Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
-          };
-
-          // if indeed we get some final extra compressed bytes to write
-          let bytes_output = inner_buffer.len();
-          if bytes_output > 0 {
-            compressed_size += bytes_output as u64;
-
-            // inner buffer -> transform writer
-            transform_writer.write(&inner_buffer).unwrap();
-
-            transform_writer.dump(true).await?;
-
-            // speed measurement
-            speed_report_current_time = self.context.performance.now();
-            speed_report_current_total_bytes = bytes_written + compressed_size;
-            speed_report_delta_time = speed_report_current_time - speed_report_last_time;
-            speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
-            if speed_report_delta_time >= self.speed_report_interval {
-              self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
-              self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
-              speed_report_last_time = speed_report_current_time;
-              speed_report_last_total_bytes = speed_report_current_total_bytes;
+        // ignore files apply to everything below the directory containing them
+        let mut child_ignore = ignore.clone();
+        if options.use_ignore_files() {
+          for ignore_file_name in IGNORE_FILE_NAMES {
+            let ignore_file_entry = children.iter().find(|child| child.is_file() && child.name() == *ignore_file_name);
+            if let Some(ignore_file_entry) = ignore_file_entry {
+              let source = ignore_file_entry.full_path();
+              let file = match js_futures::FileSystemFileEntry_file_future::from(ignore_file_entry.clone().unchecked_into::<web_sys::FileSystemFileEntry>()).await {
+                Ok(file) => file,
+                Err(e) => {
+                  state.tolerate(&source, &e, WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e)))?;
+                  continue;
+                },
+              };
+              let content = match utils::read_text(&file).await {
+                Ok(content) => content,
+                Err(e) => {
+                  state.tolerate(&source, &e, WasmError::fail_to_read(LOCATION, &format!("{:?}", e)))?;
+                  continue;
+                },
+              };
+              child_ignore = IgnoreStack::push(child_ignore, IgnoreFile::parse(&source, &content));
             }
           }
-
-          // recreate the deflate encoder
-          deflate_encoder = DeflateEncoder::new(inner_buffer, Compression::new(compression_level));
-
-          break;
         }
-      } // end of compress loop
-
-      // finalize crc32 and recreate the hasher
-      let crc32 = crc32_hasher.finalize();
-      crc32_hasher = Hasher::new();
-
-      bytes_written += compressed_size;
-
-      // write data descriptor
 
-      const DATA_DESCRIPTOR_SIZE: u32 = 4 + 4 + 8 + 8;
-
-      let mut data_descriptor_buffer = Vec::<u8>::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
-      data_descriptor_buffer.extend_from_slice(&0x08074b50_u32.to_le_bytes());
-      data_descriptor_buffer.extend_from_slice(&crc32.to_le_bytes());
-      data_descriptor_buffer.extend_from_slice(&compressed_size.to_le_bytes());
-      data_descriptor_buffer.extend_from_slice(&uncompressed_size.to_le_bytes());
-
-      // wasm buffer -> transform writer
-      transform_writer.write(&data_descriptor_buffer).unwrap();
-
-      transform_writer.dump(true).await?;
-
-      bytes_written += DATA_DESCRIPTOR_SIZE as u64;
+        (children, child_ignore)
+      };
 
-      // speed measurement
-      speed_report_current_time = self.context.performance.now();
-      speed_report_current_total_bytes = bytes_written;
-      speed_report_delta_time = speed_report_current_time - speed_report_last_time;
-      speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
-      if speed_report_delta_time >= self.speed_report_interval {
-        self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
-        self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
-        speed_report_last_time = speed_report_current_time;
-        speed_report_last_total_bytes = speed_report_current_total_bytes;
+      let child_archive_dir = file_path.archive_path.clone();
+      let subtree = Box::pin(self.scan_internal(children, state, depth + 1, child_ignore, Some(&child_archive_dir))).await?;
+      if !options.keeps_directory(&subtree) {
+        return Ok(Vec::new());
       }
 
-      // populate missing part in file header
-      file_header.set_crc_32(crc32);
-      file_header.set_compressed_size_u64(compressed_size);
-      file_header.set_uncompressed_size_u64(uncompressed_size);
-
-      file_headers.push(file_header);
-
-      self.report_compress_progress(file_headers.len(), file_headers.capacity())?;
-    } // end of file loop
-
-    // write tail data
-
-    let start_of_central_directory = bytes_written;
-
-    let mut tail_buffer = Vec::<u8>::with_capacity(32 * 1024 * 1024 as usize); // 32 MiB, should be able to hold at least 100k file/directory entries
-
-    // central directory headers
-    let number_of_file_headers = file_headers.len() as u64;
-    for file_header in file_headers {
-      file_header.write_into_as_cdh(&mut tail_buffer);
+      let mut result = Vec::with_capacity(subtree.len() + 1);
+      result.push(file_path);
+      result.extend(subtree);
+      Ok(result)
     }
+    else {
+      state.tolerate(&full_path, &JsValue::from_str("unknown file entry"), WasmError::unknown_file_entry(utils::type_name(&Handles::scan)))?;
+      Ok(Vec::new())
+    }
+  }
 
-    // the size of all central directory headers
-    let size_of_central_directory = tail_buffer.len() as u64;
-
-    let relative_offset_of_zip64_end_of_central_directory_record = start_of_central_directory + size_of_central_directory;
-
-    // zip64 end of central directory record
-    tail_buffer.extend_from_slice(&0x06064b50_u32.to_le_bytes()); // zip64 end of central dir signature
-    tail_buffer.extend_from_slice(&(2_u64 + 2_u64 + 4_u64 + 4_u64 + 8_u64 + 8_u64 + 8_u64 + 8_u64).to_le_bytes());
-    tail_buffer.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
-    tail_buffer.extend_from_slice(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes());
-    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
-    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
-    tail_buffer.extend_from_slice(&number_of_file_headers.to_le_bytes());
-    tail_buffer.extend_from_slice(&number_of_file_headers.to_le_bytes());
-    tail_buffer.extend_from_slice(&size_of_central_directory.to_le_bytes());
-    tail_buffer.extend_from_slice(&start_of_central_directory.to_le_bytes());
-
-    // zip64 end of central directory locator
-    tail_buffer.extend_from_slice(&0x07064b50_u32.to_le_bytes()); // zip64 end of central dir locator signature
-    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
-    tail_buffer.extend_from_slice(&relative_offset_of_zip64_end_of_central_directory_record.to_le_bytes());
-    tail_buffer.extend_from_slice(&1_u32.to_le_bytes()); // splitting is not supported
-
-    // End of central directory record
-    tail_buffer.extend_from_slice(&0x06054b50_u32.to_le_bytes());
-    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, number of this disk
-    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, number of the disk with the start of the central directory
-    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, total number of entries in the central directory on this disk
-    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, total number of entries in the central directory
-    tail_buffer.extend_from_slice(&0xFFFFFFFF_u32.to_le_bytes()); // zip64, size of the central directory
-    tail_buffer.extend_from_slice(&0xFFFFFFFF_u32.to_le_bytes()); // zip64, offset of start of central directory with respect to the starting disk number
-    tail_buffer.extend_from_slice(&0_u16.to_le_bytes()); // zip file comment length
-    // central_directory_buffer.extend_from_slice(&[0_u8; 0]); // zip file comment (no comment)
-
-    // tail buffer -> transform buffer
-    transform_writer.write(&tail_buffer).unwrap();
-
-    // no more data
-    transform_writer.flush().unwrap();
-
-    transform_writer.dump(false).await?;
+  /// Start a compress job sharing `pause_gate`, a pause requested while no such job ran is dropped.
+  fn begin_compress(&self, location: &str) -> Result<OperationGuard<'_>, WasmError> {
+    let was_idle = !self.operation_state.is_compressing();
+    let operation = self.operation_state.begin(Operation::Compress, location)?;
+    if was_idle {
+      self.pause_gate.resume();
+    }
+    Ok(operation)
+  }
 
-    // final speed measurement
-    speed_report_current_time = self.context.performance.now();
-    speed_report_delta_time = speed_report_current_time - speed_report_last_time;
-    self.report_average_speed(bytes_written, speed_report_current_time - speed_report_start_time)?;
-    self.report_current_speed(bytes_written - speed_report_last_total_bytes, speed_report_delta_time)?;
+  /// Snapshot the plan and the registered callbacks into a new job.
+  fn new_job(&self, pause_gate: PauseGate) -> Result<ArchiveJob, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::new_job);
 
-    if let Err(e) = utils::await_promise(writer.close()).await {
-      return Err(WasmError::fail_to_close_writer(LOCATION, &format!("{:?}", e)));
+    let file_path_list = self.context.scan_stage.file_path_list.borrow().clone();
+    if file_path_list.is_empty() {
+      return Err(WasmError::empty_file_list(LOCATION));
     }
 
-    Ok(JsValue::UNDEFINED)
+    // checked when a file is looked up, files given by `scan_files` need no file system
+    let file_system = self.context.scan_stage.file_system.borrow().clone();
+
+    Ok(ArchiveJob::new(&self.context, JobSnapshot {
+      speed_report_interval: self.speed_report_interval,
+      create_writer: self.create_writer.clone(),
+      file_path_list,
+      file_system,
+      layout: self.layout.borrow().clone(),
+      pause_gate,
+      #[cfg(feature = "worker-pool")]
+      worker_pool: self.worker_pool.borrow().clone(),
+      compress_progress: self.compress_progress.borrow().clone(),
+      byte_progress: self.byte_progress.borrow().clone(),
+      eta: self.eta.borrow().clone(),
+      average_speed: self.average_speed.borrow().clone(),
+      current_speed: self.current_speed.borrow().clone(),
+      current_file: self.current_file.borrow().clone(),
+      state_change: self.state_change.borrow().clone(),
+    }))
   }
 
   fn report_scan_progress(&self, num: usize) -> Result<(), WasmError> {
-    if let Some(scan_progress) = self.scan_progress.as_ref() {
+    let scan_progress = self.scan_progress.borrow().clone();
+    if let Some(scan_progress) = scan_progress {
       if let Err(e) = scan_progress.call1(&JsValue::NULL, &JsValue::from_f64(num as f64)) {
         return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_scan_progress), "scan_progress", &format!("{:?}", e)));
       }
@@ -424,52 +327,85 @@ impl Handles {
     Ok(())
   }
 
-  fn report_compress_progress(&self, number_of_compressed_files: usize, number_of_all_files: usize) -> Result<(), WasmError> {
-    if let Some(compress_progress) = self.compress_progress.as_ref() {
-      if let Err(e) = compress_progress.call2(
-        &JsValue::NULL,
-        &JsValue::from_f64(number_of_compressed_files as f64),
-        &JsValue::from_f64(number_of_all_files as f64)
-      ) {
-        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_compress_progress), "compress_progress", &format!("{:?}", e)));
-      }
-    }
-    Ok(())
+  fn plan_entries(&self, file_path_list: &[FilePath]) -> Vec<PlanEntry> {
+    let entry_names = self.layout.borrow().resolve(file_path_list);
+    file_path_list.iter().zip(entry_names).map(|(file_path, entry_name)| PlanEntry::new(file_path, entry_name)).collect()
   }
 
-  fn report_average_speed(&self, total_bytes_written: u64, total_time_elapsed: f64) -> Result<(), WasmError> {
-    if let Some(average_speed) = self.average_speed.as_ref() {
-      if let Err(e) = average_speed.call2(
+  fn report_scan_discovery(&self, state: &ScanState) -> Result<(), WasmError> {
+    let directories_found = state.directories_found.get();
+    let files_found = state.files_found.get();
+
+    self.report_scan_progress(directories_found + files_found)?;
+
+    let scan_discovery = self.scan_discovery.borrow().clone();
+    if let Some(scan_discovery) = scan_discovery {
+      if let Err(e) = scan_discovery.call2(
         &JsValue::NULL,
-        &JsValue::from_f64(total_bytes_written as f64),
-        &JsValue::from_f64(total_time_elapsed)
+        &JsValue::from_f64(directories_found as f64),
+        &JsValue::from_f64(files_found as f64)
       ) {
-        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_average_speed), "average_speed", &format!("{:?}", e)));
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_scan_discovery), "scan_discovery", &format!("{:?}", e)));
       }
     }
     Ok(())
   }
 
-  fn report_current_speed(&self, delta_bytes_written: u64, delta_time_elapsed: f64) -> Result<(), WasmError> {
-    if let Some(current_speed) = self.current_speed.as_ref() {
-      if let Err(e) = current_speed.call2(
+  fn report_scan_ignored(&self, path: &str, ignore_file: &str, pattern: &str) -> Result<(), WasmError> {
+    let scan_ignored = self.scan_ignored.borrow().clone();
+    if let Some(scan_ignored) = scan_ignored {
+      if let Err(e) = scan_ignored.call3(
         &JsValue::NULL,
-        &JsValue::from_f64(delta_bytes_written as f64),
-        &JsValue::from_f64(delta_time_elapsed)
+        &JsValue::from_str(path),
+        &JsValue::from_str(ignore_file),
+        &JsValue::from_str(pattern)
       ) {
-        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_current_speed), "current_speed", &format!("{:?}", e)));
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_scan_ignored), "scan_ignored", &format!("{:?}", e)));
       }
     }
     Ok(())
   }
 
-  fn report_current_file(&self, path: &str) -> Result<(), WasmError> {
-    if let Some(current_file) = self.current_file.as_ref() {
-      if let Err(e) = current_file.call1(&JsValue::NULL, &JsValue::from_str(path)) {
-        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_current_file), "current_file", &format!("{:?}", e)));
+  fn invoke_scan_filter(&self, file_path: &FilePath) -> Result<ScanFilterResult, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::invoke_scan_filter);
+
+    // a copy, the callback may register callbacks
+    let Some(scan_filter) = self.scan_filter.borrow().clone() else {
+      return Ok(ScanFilterResult::Keep);
+    };
+
+    let info = js_sys::Object::new();
+    let optional_number = |value: Option<f64>| value.map_or(JsValue::UNDEFINED, JsValue::from_f64);
+    let _ = js_sys::Reflect::set(&info, &JsValue::from_str("path"), &JsValue::from_str(&file_path.path));
+    let _ = js_sys::Reflect::set(&info, &JsValue::from_str("isDir"), &JsValue::from_bool(file_path.is_dir));
+    let _ = js_sys::Reflect::set(&info, &JsValue::from_str("size"), &optional_number(file_path.size.map(|size| size as f64)));
+    let _ = js_sys::Reflect::set(&info, &JsValue::from_str("lastModified"), &optional_number(file_path.last_modified));
+
+    let result = match scan_filter.call1(&JsValue::NULL, &info) {
+      Ok(result) => result,
+      Err(e) => return Err(WasmError::fail_to_invoke_callback(LOCATION, "scan_filter", &format!("{:?}", e))),
+    };
+
+    if result.is_undefined() || result.is_null() {
+      return Ok(ScanFilterResult::Keep);
+    }
+    if let Some(decision) = result.as_string() {
+      return match decision.as_str() {
+        "skip" => Ok(ScanFilterResult::Skip),
+        "keep" => Ok(ScanFilterResult::Keep),
+        _ => Err(WasmError::invalid_scan_filter_result(LOCATION, &file_path.path, &decision)),
+      };
+    }
+    if result.is_object() {
+      if let Some(archive_path) = js_sys::Reflect::get(&result, &JsValue::from_str("archivePath")).ok().and_then(|value| value.as_string()) {
+        return match utils::normalize_archive_path(&archive_path) {
+          Some(archive_path) => Ok(ScanFilterResult::Remap(archive_path)),
+          None => Err(WasmError::invalid_archive_path(LOCATION, &archive_path)),
+        };
       }
     }
-    Ok(())
+    Err(WasmError::invalid_scan_filter_result(LOCATION, &file_path.path, &format!("{:?}", result)))
   }
 }
 
@@ -480,26 +416,135 @@ impl Handles {
   /// # Parameters
   ///
   /// * `entries` - MUST be an array of `FileSystemEntry`
+  /// * `signal` - an optional `AbortSignal`, once aborted the scan stops
   ///
   /// # Returns
   ///
   /// - resolve: number of scanned entries
   /// - reject: a `WasmError` object
-  pub async fn scan(&self, entries: js_sys::Array) -> Result<JsValue, WasmError> {
+  pub async fn scan(&self, entries: js_sys::Array, signal: Option<web_sys::AbortSignal>) -> Result<JsValue, WasmError> {
+    let mut options = ScanOptions::default();
+    if let Some(signal) = signal {
+      options.set_signal(signal);
+    }
+    let report = self.scan_with_options(entries, options).await?;
+    Ok(JsValue::from_f64(report.entries as f64))
+  }
+
+  /// Do a deep scan on input entries, only collecting entries allowed by `options`.
+  ///
+  /// # Parameters
+  ///
+  /// * `entries` - MUST be an array of `FileSystemEntry`
+  /// * `options` - a `ScanOptions` object, it is consumed by this call
+  ///
+  /// # Returns
+  ///
+  /// - resolve: a `ScanReport` object, with the scanned plan and the problems met in tolerant mode
+  /// - reject: a `WasmError` object
+  pub async fn scan_with_options(&self, entries: js_sys::Array, options: ScanOptions) -> Result<ScanReport, WasmError> {
+    let _operation = self.operation_state.begin(Operation::Scan, utils::type_name(&Handles::scan_with_options))?;
+
     self.context.scan_stage.file_path_list.borrow_mut().clear();
     self.context.scan_stage.file_system.borrow_mut().take();
 
     let first_entry = entries.get(0);
 
     if first_entry.eq(&JsValue::UNDEFINED) {
-      return Err(WasmError::empty_file_list(utils::type_name(&Handles::scan)));
+      return Err(WasmError::empty_file_list(utils::type_name(&Handles::scan_with_options)));
     }
 
     self.context.scan_stage.file_system.replace(Some(first_entry.unchecked_into::<web_sys::FileSystemEntry>().filesystem()));
 
-    self.scan_internal(entries).await?;
+    let entries = entries.iter().map(|entry| entry.unchecked_into::<web_sys::FileSystemEntry>()).collect();
+    let state = ScanState {
+      options: &options,
+      semaphore: Semaphore::new(options.concurrency()),
+      directories_found: Cell::new(0),
+      files_found: Cell::new(0),
+      problems: RefCell::new(Vec::new()),
+    };
+    let file_path_list = self.scan_internal(entries, &state, 0, None, None).await?;
+
+    // problems are recorded in completion order, which depends on timing
+    let mut problems = state.problems.into_inner();
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
 
-    Ok(JsValue::from_f64(self.context.scan_stage.file_path_list.borrow().len() as f64))
+    let plan = self.plan_entries(&file_path_list);
+
+    let entries = file_path_list.len() as u32;
+    self.context.scan_stage.file_path_list.replace(file_path_list);
+
+    Ok(ScanReport { entries, plan, problems })
+  }
+
+  /// Replace the plan with files which are already at hand, e.g. inside a Worker, where `FileSystemEntry`s can not be sent.
+  ///
+  /// Missing parent directories are added before their first child, the order of `files` is kept otherwise.
+  ///
+  /// # Parameters
+  ///
+  /// * `files` - an array of `{ path: string, file?: File }`, `path` like `a/b.txt` is the path inside the zip file,
+  ///   an element without `file` is a directory
+  ///
+  /// # Returns
+  ///
+  /// - number of entries in the plan
+  /// - a `WasmError` whose code is `0x14` or `0x16` if a path is invalid or listed twice
+  pub fn scan_files(&self, files: js_sys::Array) -> Result<u32, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::scan_files);
+
+    let _operation = self.operation_state.begin(Operation::Scan, LOCATION)?;
+
+    if files.length() == 0 {
+      return Err(WasmError::empty_file_list(LOCATION));
+    }
+
+    let mut file_path_list = Vec::<FilePath>::with_capacity(files.length() as usize);
+    let mut seen = HashSet::<String>::new();
+    for element in files.iter() {
+      let path = js_sys::Reflect::get(&element, &JsValue::from_str("path")).unwrap_or(JsValue::UNDEFINED).as_string().unwrap_or_default();
+      let Some(archive_path) = utils::normalize_archive_path(&path) else {
+        return Err(WasmError::invalid_archive_path(LOCATION, &path));
+      };
+      let file = match js_sys::Reflect::get(&element, &JsValue::from_str("file")) {
+        Ok(file) if file.is_undefined() => None,
+        Ok(file) => match file.dyn_into::<web_sys::File>() {
+          Ok(file) => Some(file),
+          Err(_) => return Err(WasmError::dynamic_cast_error(LOCATION, "JsValue", "File")),
+        },
+        Err(_) => None,
+      };
+
+      // parents first, a directory listed after its children keeps its earlier place
+      for (position, _) in archive_path.match_indices('/') {
+        let parent = &archive_path[..position];
+        if seen.insert(parent.to_owned()) {
+          file_path_list.push(FilePath { path: format!("/{}", parent), is_dir: true, size: None, last_modified: None, archive_path: parent.to_owned(), file: None });
+        }
+      }
+      if !seen.insert(archive_path.clone()) {
+        if file.is_none() && file_path_list.iter().any(|file_path| file_path.is_dir && file_path.archive_path == archive_path) {
+          continue;
+        }
+        return Err(WasmError::duplicate_archive_path(LOCATION, &archive_path));
+      }
+
+      file_path_list.push(FilePath {
+        path: format!("/{}", archive_path),
+        is_dir: file.is_none(),
+        size: file.as_ref().map(|file| file.size() as u64),
+        last_modified: file.as_ref().map(|file| file.last_modified()),
+        archive_path,
+        file,
+      });
+    }
+
+    let entries = file_path_list.len() as u32;
+    self.context.scan_stage.file_system.borrow_mut().take();
+    self.context.scan_stage.file_path_list.replace(file_path_list);
+    Ok(entries)
   }
 
   /// Compress scanned entries.
@@ -508,13 +553,50 @@ impl Handles {
   ///
   /// * `output_file_name` - name of output zip file
   /// * `compression_level` - an integer, minimal 0, maximum 9
+  /// * `signal` - an optional `AbortSignal`, once aborted the writer is aborted too
   ///
   /// # Returns
   ///
-  /// - resolve: undefined
+  /// - resolve: a `JobSummary` object
   /// - reject: a `WasmError` object
-  pub async fn compress(&self, output_file_name: String, compression_level: u32) -> Result<JsValue, WasmError> {
-    self.compress_internal(output_file_name, compression_level, None).await
+  pub async fn compress(&self, output_file_name: String, compression_level: u32, signal: Option<web_sys::AbortSignal>) -> Result<JobSummary, WasmError> {
+    let _operation = self.begin_compress(utils::type_name(&Self::compress))?;
+    self.new_job(self.pause_gate.clone())?.compress_internal(CompressOptions::new(output_file_name, compression_level, signal)).await
+  }
+
+  /// Compress scanned entries, like `compress` but with a `CompressOptions` object.
+  ///
+  /// # Parameters
+  ///
+  /// * `options` - a `CompressOptions` object, validated before anything is created
+  ///
+  /// # Returns
+  ///
+  /// - resolve: a `JobSummary` object
+  /// - reject: a `WasmError` object, unknown or invalid options are reported with code `0x1B` or `0x1C`
+  pub async fn compress_with_options(&self, options: CompressOptionsObject) -> Result<JobSummary, WasmError> {
+    let options = CompressOptions::parse(&options)?;
+    let _operation = self.begin_compress(utils::type_name(&Self::compress_with_options))?;
+    self.new_job(self.pause_gate.clone())?.compress_internal(options).await
+  }
+
+  /// Estimate the output size and duration of compressing scanned entries, nothing is written and no writer is created.
+  ///
+  /// Up to 16 MiB are read from up to 32 files spread over the plan and compressed with the settings of `options`,
+  /// the duration is that of compressing on this thread, a worker pool may be faster.
+  ///
+  /// # Parameters
+  ///
+  /// * `options` - a `CompressOptions` object, the same as given to `compress_with_options`
+  ///
+  /// # Returns
+  ///
+  /// - resolve: an `Estimate` object
+  /// - reject: a `WasmError` object, unknown or invalid options are reported with code `0x1B` or `0x1C`
+  pub async fn estimate(&self, options: CompressOptionsObject) -> Result<Estimate, WasmError> {
+    let options = CompressOptions::parse(&options)?;
+    self.operation_state.check(Operation::Compress, utils::type_name(&Self::estimate))?;
+    self.new_job(PauseGate::new())?.estimate_internal(options).await
   }
 
   /// Compress scanned entries and transform output bytes.
@@ -524,13 +606,25 @@ impl Handles {
   /// * `output_file_name` - name of output zip file
   /// * `compression_level` - an integer, minimal 0, maximum 9
   /// * `transform_script` - NOT IMPLEMENTED
+  /// * `signal` - an optional `AbortSignal`, once aborted the writer is aborted too
   ///
   /// # Returns
   ///
-  /// - resolve: undefined
+  /// - resolve: a `JobSummary` object
   /// - reject: a `WasmError` object
-  pub async fn compress_transform(&self, output_file_name: String, compression_level: u32, transform_script: String) -> Result<JsValue, WasmError> {
-    self.compress_internal(output_file_name, compression_level, Some(transform_script)).await
+  pub async fn compress_transform(&self, output_file_name: String, compression_level: u32, transform_script: String, signal: Option<web_sys::AbortSignal>) -> Result<JobSummary, WasmError> {
+    let _operation = self.begin_compress(utils::type_name(&Self::compress_transform))?;
+    let options = CompressOptions { transform_script: Some(transform_script), ..CompressOptions::new(output_file_name, compression_level, signal) };
+    self.new_job(self.pause_gate.clone())?.compress_internal(options).await
+  }
+
+  /// Create a job compressing a snapshot of the current plan with the callbacks registered so far,
+  /// later edits of the plan or the callbacks do not affect it.
+  ///
+  /// Jobs created this way are not affected by `Handles.pause`, use `ArchiveJob.pause` instead.
+  pub fn create_job(&self) -> Result<ArchiveJob, WasmError> {
+    self.operation_state.check(Operation::Compress, utils::type_name(&Self::create_job))?;
+    self.new_job(PauseGate::new())
   }
 
   /// Just transform the input file, only one file could be accepted.
@@ -538,42 +632,36 @@ impl Handles {
   /// # Parameters
   ///
   /// * `transform_script` - NOT IMPLEMENTED
+  /// * `signal` - an optional `AbortSignal`, once aborted the writer is aborted too
   ///
   /// # Returns
   ///
   /// - resolve: undefined
   /// - reject: a `WasmError` object
-  pub async fn transform(&self, _transform_script: String) -> Result<JsValue, WasmError> {
+  pub async fn transform(&self, _transform_script: String, signal: Option<web_sys::AbortSignal>) -> Result<JsValue, WasmError> {
     #[allow(non_snake_case)]
     let LOCATION = utils::type_name(&Self::recover);
 
-    let file_path_list = self.context.scan_stage.file_path_list.borrow();
-    if file_path_list.len() == 0 {
-      return Err(WasmError::empty_file_list(LOCATION));
-    }
-    if file_path_list.len() > 1 {
-      return Err(WasmError::too_many_files(LOCATION));
-    }
-    let FilePath { path, is_dir } = file_path_list.first().unwrap();
-
-    if *is_dir {
-      return Err(WasmError::can_not_transform_directory(LOCATION, path));
-    }
+    let _operation = self.operation_state.begin(Operation::Transform, LOCATION)?;
 
-    let file_system = self.context.scan_stage.file_system.borrow();
-    let Some(file_system) = file_system.as_ref() else {
-      return Err(WasmError::missing_file_system(LOCATION));
+    // a copy, the plan is not borrowed across awaits
+    let file_path = {
+      let file_path_list = self.context.scan_stage.file_path_list.borrow();
+      if file_path_list.is_empty() {
+        return Err(WasmError::empty_file_list(LOCATION));
+      }
+      if file_path_list.len() > 1 {
+        return Err(WasmError::too_many_files(LOCATION));
+      }
+      file_path_list.first().unwrap().clone()
     };
 
-    let file_entry = match js_futures::FileSystemDirectoryEntry_getFile_future::from(file_system.root(), path).await {
-      Ok(entry) => entry,
-      Err(e) => return Err(WasmError::fail_to_get_file_entry(LOCATION, &format!("{:?}", e))),
-    };
+    if file_path.is_dir {
+      return Err(WasmError::can_not_transform_directory(LOCATION, &file_path.path));
+    }
 
-    let file = match js_futures::FileSystemFileEntry_file_future::from(file_entry).await {
-      Ok(file) => file,
-      Err(e) => return Err(WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e))),
-    };
+    let file_system = self.context.scan_stage.file_system.borrow().clone();
+    let file = file_path.open(file_system.as_ref(), LOCATION).await?;
 
     let file_name = file.name();
 
@@ -604,13 +692,17 @@ impl Handles {
     const TRANSFORM_BUFFER_WRITE_THRESHOLD: usize = 16 * 1024 * 1024;
     let mut transform_writer = TransformWriter::new(
       &writer,
+      &self.context.buffer_pool,
       TRANSFORM_BUFFER_WRITE_THRESHOLD,
       8,
-      16 * 1024 * 1024,
+      Vec::with_capacity(16 * 1024 * 1024),
       false,
+      OutputLimits::default(),
     );
 
     loop {
+      utils::check_signal_and_abort_writer(signal.as_ref(), &writer, LOCATION).await?;
+
       // js reader -> js buffer
       let ReadResult { new_buffer: array_buffer_new_read, view: view_read, done } = utils::byob_read(&array_buffer_read, &reader).await?;
 
@@ -625,7 +717,7 @@ impl Handles {
         view_read.copy_to(wasm_slice);
 
         // wasm buffer slice -> transform writer
-        transform_writer.write(wasm_slice).unwrap();
+        transform_writer.write_all(wasm_slice).unwrap();
 
         transform_writer.dump(true).await?;
       }
@@ -651,42 +743,36 @@ impl Handles {
   /// # Parameters
   ///
   /// * `transform_script` - NOT IMPLEMENTED
+  /// * `signal` - an optional `AbortSignal`, once aborted the writer is aborted too
   ///
   /// # Returns
   ///
   /// - resolve: undefined
   /// - reject: a `WasmError` object
-  pub async fn recover(&self, _transform_script: String) -> Result<JsValue, WasmError> {
+  pub async fn recover(&self, _transform_script: String, signal: Option<web_sys::AbortSignal>) -> Result<JsValue, WasmError> {
     #[allow(non_snake_case)]
     let LOCATION = utils::type_name(&Self::recover);
 
-    let file_path_list = self.context.scan_stage.file_path_list.borrow();
-    if file_path_list.len() == 0 {
-      return Err(WasmError::empty_file_list(LOCATION));
-    }
-    if file_path_list.len() > 1 {
-      return Err(WasmError::too_many_files(LOCATION));
-    }
-    let FilePath { path, is_dir } = file_path_list.first().unwrap();
-
-    if *is_dir {
-      return Err(WasmError::can_not_recover_directory(LOCATION, path));
-    }
+    let _operation = self.operation_state.begin(Operation::Recover, LOCATION)?;
 
-    let file_system = self.context.scan_stage.file_system.borrow();
-    let Some(file_system) = file_system.as_ref() else {
-      return Err(WasmError::missing_file_system(LOCATION));
+    // a copy, the plan is not borrowed across awaits
+    let file_path = {
+      let file_path_list = self.context.scan_stage.file_path_list.borrow();
+      if file_path_list.is_empty() {
+        return Err(WasmError::empty_file_list(LOCATION));
+      }
+      if file_path_list.len() > 1 {
+        return Err(WasmError::too_many_files(LOCATION));
+      }
+      file_path_list.first().unwrap().clone()
     };
 
-    let file_entry = match js_futures::FileSystemDirectoryEntry_getFile_future::from(file_system.root(), path).await {
-      Ok(entry) => entry,
-      Err(e) => return Err(WasmError::fail_to_get_file_entry(LOCATION, &format!("{:?}", e))),
-    };
+    if file_path.is_dir {
+      return Err(WasmError::can_not_recover_directory(LOCATION, &file_path.path));
+    }
 
-    let file = match js_futures::FileSystemFileEntry_file_future::from(file_entry).await {
-      Ok(file) => file,
-      Err(e) => return Err(WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e))),
-    };
+    let file_system = self.context.scan_stage.file_system.borrow().clone();
+    let file = file_path.open(file_system.as_ref(), LOCATION).await?;
 
     let file_name = file.name();
 
@@ -724,6 +810,8 @@ impl Handles {
     );
 
     loop {
+      utils::check_signal_and_abort_writer(signal.as_ref(), &writer, LOCATION).await?;
+
       // js reader -> js buffer
       let ReadResult { new_buffer: array_buffer_new_read, view: view_read, done } = utils::byob_read(&array_buffer_read, &reader).await?;
 
@@ -738,7 +826,7 @@ impl Handles {
         view_read.copy_to(wasm_slice);
 
         // wasm buffer slice -> transform writer
-        recover_writer.write(wasm_slice).unwrap();
+        recover_writer.write_all(wasm_slice).unwrap();
 
         recover_writer.dump(true).await?;
       }
@@ -759,13 +847,127 @@ impl Handles {
     Ok(JsValue::UNDEFINED)
   }
 
+  /// Get the scanned entries in the order they will be compressed.
+  ///
+  /// # Returns
+  ///
+  /// an array of `PlanEntry`
+  pub fn plan(&self) -> Vec<PlanEntry> {
+    self.plan_entries(&self.context.scan_stage.file_path_list.borrow())
+  }
+
+  /// Count scanned files, directories and bytes.
+  pub fn plan_totals(&self) -> PlanTotals {
+    plan::totals(&self.context.scan_stage.file_path_list.borrow())
+  }
+
+  /// Remove an entry from the plan, a directory is removed together with everything stored under it.
+  ///
+  /// # Parameters
+  ///
+  /// * `path` - the `path` of a `PlanEntry`
+  ///
+  /// # Returns
+  ///
+  /// number of removed entries
+  pub fn remove_entry(&self, path: &str) -> Result<u32, WasmError> {
+    self.operation_state.check(Operation::EditPlan, utils::type_name(&Self::remove_entry))?;
+    plan::remove(&mut self.context.scan_stage.file_path_list.borrow_mut(), path).map(|number| number as u32)
+  }
+
+  /// Change where an entry is stored inside the zip file, a directory takes everything under it along.
+  ///
+  /// # Parameters
+  ///
+  /// * `path` - the `path` of a `PlanEntry`
+  /// * `archive_path` - the new path inside the zip file, like `a/b/c.txt`
+  pub fn rename_entry(&self, path: &str, archive_path: &str) -> Result<(), WasmError> {
+    self.operation_state.check(Operation::EditPlan, utils::type_name(&Self::rename_entry))?;
+    plan::rename(&mut self.context.scan_stage.file_path_list.borrow_mut(), path, archive_path)
+  }
+
+  /// Move an entry under another folder inside the zip file, keeping its name.
+  ///
+  /// # Parameters
+  ///
+  /// * `path` - the `path` of a `PlanEntry`
+  /// * `folder` - a folder inside the zip file like `a/b`, an empty string means the root
+  pub fn move_entry(&self, path: &str, folder: &str) -> Result<(), WasmError> {
+    self.operation_state.check(Operation::EditPlan, utils::type_name(&Self::move_entry))?;
+    plan::move_under(&mut self.context.scan_stage.file_path_list.borrow_mut(), path, folder)
+  }
+
+  /// Drop buffers kept for reuse by later compress jobs, buffers of running jobs are not affected.
+  pub fn release_buffers(&self) {
+    self.context.buffer_pool.clear();
+  }
+
+  /// What is running now, one of `"idle"`, `"scanning"`, `"compressing"`, `"transforming"` and `"recovering"`.
+  ///
+  /// Operations which would interfere with a running one reject with a `WasmError` whose code is `0x1A`.
+  pub fn state(&self) -> String {
+    self.operation_state.current().to_owned()
+  }
+
+  /// Suspend every running `compress` and `compress_transform` at its next safe point, buffers and compression state are kept.
+  ///
+  /// Only running jobs are paused, a job started while none runs begins unpaused.
+  pub fn pause(&self) {
+    self.pause_gate.pause();
+  }
+
+  /// Continue what `pause` suspended.
+  pub fn resume(&self) {
+    self.pause_gate.resume();
+  }
+
+  /// Set how archive paths of the plan are turned into entry names inside the zip file,
+  /// see `PlanEntry.output_path` for the result.
+  ///
+  /// # Parameters
+  ///
+  /// * `layout` - a `LayoutOptions` object, it is consumed by this call
+  pub fn set_layout(&self, layout: LayoutOptions) {
+    self.layout.replace(layout);
+  }
+
   /// Register "scan_progress" callback.
   ///
   /// # Parameters
   ///
   /// * `callback` - a function like `(number_of_scanned_entries: number) => {}`
-  pub fn register_scan_progress(&mut self, callback: js_sys::Function) {
-    self.scan_progress = Some(callback);
+  pub fn register_scan_progress(&self, callback: js_sys::Function) {
+    self.scan_progress.replace(Some(callback));
+  }
+
+  /// Register "scan_discovery" callback.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(number_of_directories: number, number_of_files: number) => {}`
+  pub fn register_scan_discovery(&self, callback: js_sys::Function) {
+    self.scan_discovery.replace(Some(callback));
+  }
+
+  /// Register "scan_ignored" callback, invoked for each entry left out by an ignore file.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(path: string, ignore_file: string, pattern: string) => {}`
+  pub fn register_scan_ignored(&self, callback: js_sys::Function) {
+    self.scan_ignored.replace(Some(callback));
+  }
+
+  /// Register "scan_filter" callback, invoked for each discovered entry which passed `ScanOptions` and ignore files.
+  ///
+  /// Registering this callback makes `scan` fetch the `File` of every file entry to know its size and modification time.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(entry: { path: string, isDir: boolean, size?: number, lastModified?: number }) => "skip" | "keep" | { archivePath: string }`,
+  ///                skipped directories are not read, returning `undefined` is the same as `"keep"`
+  pub fn register_scan_filter(&self, callback: js_sys::Function) {
+    self.scan_filter.replace(Some(callback));
   }
 
   /// Register "compress_progress" callback.
@@ -773,8 +975,30 @@ impl Handles {
   /// # Parameters
   ///
   /// * `callback` - a function like `(number_of_compressed_files: number, number_of_all_files: number) => {}`
-  pub fn register_compress_progress(&mut self, callback: js_sys::Function) {
-    self.compress_progress = Some(callback);
+  pub fn register_compress_progress(&self, callback: js_sys::Function) {
+    self.compress_progress.replace(Some(callback));
+  }
+
+  /// Register "byte_progress" callback, invoked after each read.
+  ///
+  /// Registering this callback makes `compress` look up the size of every file whose size was not collected during scan.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(total_bytes_read: number, total_bytes: number, current_file_bytes_read: number, current_file_size: number) => {}`
+  pub fn register_byte_progress(&self, callback: js_sys::Function) {
+    self.byte_progress.replace(Some(callback));
+  }
+
+  /// Register "eta" callback, invoked along with "average_speed".
+  ///
+  /// Registering this callback makes `compress` look up the size of every file whose size was not collected during scan.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(remaining_time: number, bytes_read_per_ms: number) => {}`, `remaining_time` is in ms
+  pub fn register_eta(&self, callback: js_sys::Function) {
+    self.eta.replace(Some(callback));
   }
 
   /// Register "average_speed" callback.
@@ -782,8 +1006,8 @@ impl Handles {
   /// # Parameters
   ///
   /// * `callback` - a function like `(total_bytes_written: number, total_time_elapsed: number) => {}`
-  pub fn register_average_speed(&mut self, callback: js_sys::Function) {
-    self.average_speed = Some(callback);
+  pub fn register_average_speed(&self, callback: js_sys::Function) {
+    self.average_speed.replace(Some(callback));
   }
 
   /// Register "current_speed" callback.
@@ -791,8 +1015,17 @@ impl Handles {
   /// # Parameters
   ///
   /// * `callback` - a function like `(delta_bytes_written: number, delta_time_elapsed: number) => {}`
-  pub fn register_current_speed(&mut self, callback: js_sys::Function) {
-    self.current_speed = Some(callback);
+  pub fn register_current_speed(&self, callback: js_sys::Function) {
+    self.current_speed.replace(Some(callback));
+  }
+
+  /// Register "state_change" callback, invoked when `compress` actually suspends and when it continues.
+  ///
+  /// # Parameters
+  ///
+  /// * `callback` - a function like `(state: "paused" | "resumed") => {}`
+  pub fn register_state_change(&self, callback: js_sys::Function) {
+    self.state_change.replace(Some(callback));
   }
 
   /// Register "current_current_filespeed" callback.
@@ -800,7 +1033,45 @@ impl Handles {
   /// # Parameters
   ///
   /// * `callback` - a function like `(path: string) => {}`
-  pub fn register_current_file(&mut self, callback: js_sys::Function) {
-    self.current_file = Some(callback);
+  pub fn register_current_file(&self, callback: js_sys::Function) {
+    self.current_file.replace(Some(callback));
+  }
+}
+
+#[cfg(feature = "worker-pool")]
+#[wasm_bindgen]
+impl Handles {
+  /// Compress files on Web Workers, only available in builds with the `worker-pool` feature.
+  ///
+  /// Jobs created afterwards send every file to the pool in blocks, a large file is split so that it keeps every worker
+  /// busy, and entries are written in plan order. Finished blocks held by a job take what its other buffers
+  /// leave of its memory budget. Stored files, level 10, compression rules and the auto level are still compressed by the job itself.
+  ///
+  /// Replacing the pool terminates the old workers once no running job uses them.
+  ///
+  /// * `create_worker` - `() => Worker`, the worker must run `js/compress_worker.js` or an equivalent script
+  /// * `size` - number of workers, `0` removes the pool
+  pub fn set_worker_pool(&self, create_worker: js_sys::Function, size: u32) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::set_worker_pool);
+
+    let mut workers = Vec::<web_sys::Worker>::with_capacity(size as usize);
+    for _ in 0..size {
+      let worker = match create_worker.call0(&JsValue::NULL) {
+        Ok(worker) => worker.dyn_into::<web_sys::Worker>().map_err(|_| WasmError::dynamic_cast_error(LOCATION, "JsValue", "Worker")),
+        Err(e) => Err(WasmError::fail_to_invoke_callback(LOCATION, "create_worker", &format!("{:?}", e))),
+      };
+      match worker {
+        Ok(worker) => workers.push(worker),
+        Err(e) => {
+          // the current pool is kept, workers created so far are not
+          workers.iter().for_each(web_sys::Worker::terminate);
+          return Err(e);
+        },
+      }
+    }
+
+    self.worker_pool.replace(if workers.is_empty() { None } else { Some(Rc::new(WorkerPool::new(workers))) });
+    Ok(())
   }
 }
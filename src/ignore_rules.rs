@@ -0,0 +1,168 @@
+//! Hierarchical `.gitignore` style rules, loaded from ignore files found while scanning.
+//!
+//! Rules follow gitignore semantics:
+//!
+//! * blank lines and lines starting with '#' are skipped
+//! * a leading '!' re-includes entries ignored by an earlier rule
+//! * a trailing '/' makes the rule only match directories
+//! * a rule containing a '/' (other than a trailing one) is relative to the directory of its ignore file,
+//!   otherwise it matches entry names at any depth below that directory
+//! * rules in deeper ignore files, and later rules in the same file, take precedence
+//! * a directory is also matched with a trailing '/', so `foo/**` ignores `foo` itself
+
+use std::rc::Rc;
+
+use crate::glob::Glob;
+
+/// Ignore files recognized in every scanned directory, later ones take precedence.
+pub const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".zipignore"];
+
+struct IgnoreRule {
+  pattern: String,
+  glob: Glob,
+  negated: bool,
+  dir_only: bool,
+}
+
+pub struct IgnoreFile {
+  /// full path of the ignore file
+  source: String,
+  /// path of the directory containing the ignore file, without leading or trailing slash
+  base: String,
+  rules: Vec<IgnoreRule>,
+}
+
+/// One layer per loaded ignore file, linked to the layers of parent directories.
+pub struct IgnoreStack {
+  file: IgnoreFile,
+  parent: Option<Rc<IgnoreStack>>,
+}
+
+/// Which rule decided an entry is ignored.
+pub struct IgnoreMatch<'a> {
+  pub source: &'a str,
+  pub pattern: &'a str,
+}
+
+impl IgnoreFile {
+  /// Parse the content of an ignore file, lines which are not valid globs are skipped.
+  pub fn parse(source: &str, content: &str) -> Self {
+    let source = source.to_owned();
+    let base = match source.trim_start_matches('/').rfind('/') {
+      Some(index) => source.trim_start_matches('/')[..index].to_owned(),
+      None => String::new(),
+    };
+
+    let mut rules = Vec::new();
+    for line in content.lines() {
+      let line = line.trim_end_matches('\r');
+      let line = if line.ends_with("\\ ") { line } else { line.trim_end() };
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let (negated, body) = match line.strip_prefix('!') {
+        Some(body) => (true, body),
+        None => (false, line),
+      };
+      let (dir_only, body) = match body.strip_suffix('/') {
+        Some(body) => (true, body),
+        None => (false, body),
+      };
+      if body.is_empty() {
+        continue;
+      }
+
+      let Ok(glob) = Glob::new(body) else {
+        continue;
+      };
+      rules.push(IgnoreRule { pattern: line.to_owned(), glob, negated, dir_only });
+    }
+
+    Self { source, base, rules }
+  }
+
+  /// `Some(true)` if ignored, `Some(false)` if explicitly re-included, `None` if no rule matches.
+  fn check(&self, path: &str, is_dir: bool) -> Option<(bool, &IgnoreRule)> {
+    let relative = if self.base.is_empty() {
+      path
+    } else {
+      path.strip_prefix(&self.base)?.strip_prefix('/')?
+    };
+
+    self.rules.iter()
+      .rev()
+      .filter(|rule| is_dir || !rule.dir_only)
+      .find(|rule| rule.glob.is_match_entry(relative, is_dir))
+      .map(|rule| (!rule.negated, rule))
+  }
+}
+
+impl IgnoreStack {
+  pub fn push(parent: Option<Rc<IgnoreStack>>, file: IgnoreFile) -> Option<Rc<IgnoreStack>> {
+    Some(Rc::new(Self { file, parent }))
+  }
+
+  /// `path` is the full path of the entry without the leading slash.
+  pub fn check<'a>(stack: &'a Option<Rc<IgnoreStack>>, path: &str, is_dir: bool) -> Option<IgnoreMatch<'a>> {
+    let mut layer = stack.as_deref();
+    while let Some(current) = layer {
+      if let Some((ignored, rule)) = current.file.check(path, is_dir) {
+        if !ignored {
+          return None;
+        }
+        return Some(IgnoreMatch { source: &current.file.source, pattern: &rule.pattern });
+      }
+      layer = current.parent.as_deref();
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stack(files: &[(&str, &str)]) -> Option<Rc<IgnoreStack>> {
+    files.iter().fold(None, |parent, (source, content)| IgnoreStack::push(parent, IgnoreFile::parse(source, content)))
+  }
+
+  fn is_ignored(stack: &Option<Rc<IgnoreStack>>, path: &str, is_dir: bool) -> bool {
+    IgnoreStack::check(stack, path, is_dir).is_some()
+  }
+
+  #[test]
+  fn negation_and_directory_rules() {
+    let stack = stack(&[("/root/.gitignore", "# logs\n*.log\n!keep.log\nbuild/\ncache/**\n")]);
+    assert!(is_ignored(&stack, "root/a/b.log", false));
+    assert!(!is_ignored(&stack, "root/a/keep.log", false));
+    assert!(is_ignored(&stack, "root/build", true));
+    assert!(!is_ignored(&stack, "root/build", false));
+    // the directory itself is pruned, not only what is under it
+    assert!(is_ignored(&stack, "root/cache", true));
+    assert!(is_ignored(&stack, "root/cache/x/y", false));
+    assert_eq!(IgnoreStack::check(&stack, "root/x.log", false).map(|ignore_match| ignore_match.pattern), Some("*.log"));
+  }
+
+  #[test]
+  fn rules_with_a_slash_are_anchored_to_their_directory() {
+    let stack = stack(&[("/root/.gitignore", "/top.txt\ndoc/*.md\n")]);
+    assert!(is_ignored(&stack, "root/top.txt", false));
+    assert!(!is_ignored(&stack, "root/sub/top.txt", false));
+    assert!(is_ignored(&stack, "root/doc/a.md", false));
+    assert!(!is_ignored(&stack, "root/sub/doc/a.md", false));
+    assert!(!is_ignored(&stack, "other/top.txt", false));
+  }
+
+  #[test]
+  fn deeper_files_take_precedence() {
+    let stack = stack(&[
+      ("/root/.gitignore", "*.tmp\n"),
+      ("/root/sub/.gitignore", "!*.tmp\n"),
+      ("/root/sub/.zipignore", "secret.tmp\n"),
+    ]);
+    assert!(is_ignored(&stack, "root/a.tmp", false));
+    assert!(!is_ignored(&stack, "root/sub/a.tmp", false));
+    assert!(is_ignored(&stack, "root/sub/secret.tmp", false));
+  }
+}
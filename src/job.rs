@@ -0,0 +1,1045 @@
+//! The type `ArchiveJob` is one compression of a snapshot of the plan, several jobs can run at once.
+//!
+//! A job owns everything it touches while running: the plan, the file system, the layout, the callbacks and the buffers,
+//! so editing the plan or starting another job does not affect it.
+
+use std::io::Write;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crc32fast::Hasher;
+
+use crate::auto_level::{AutoLevel, MAX_SAMPLE_PER_FILE};
+use crate::buffer_pool::{BufferPool, MemoryBudget, HEADER_BUFFER_SIZE, READ_BUFFERS};
+use crate::central_directory::CentralDirectory;
+use crate::codec::{self, CodecKind, Codecs, EntryCodec};
+use crate::compress_rules::SNIFF_LENGTH;
+use crate::compress_options::{CompressOptions, CompressOptionsObject};
+use crate::estimate::{self, Estimate, Estimator, MAX_SAMPLED_FILES, MAX_SAMPLE_BYTES, MIN_SAMPLE_PER_FILE};
+use crate::constant::{VERSION_MADE_BY, VERSION_NEEDED_TO_EXTRACT};
+use crate::output_limits::OutputLimits;
+use crate::transform_writer::TransformWriter;
+use crate::utils::ReadResult;
+use crate::{appnote63, prelude::*, utils};
+use crate::context::{Context, FilePath};
+use crate::layout::LayoutOptions;
+use crate::pause_gate::PauseGate;
+use crate::read_ahead::{self, OpenedFile, ReadAhead};
+use crate::semaphore::Semaphore;
+use crate::wasm_error::WasmError;
+#[cfg(feature = "worker-pool")]
+use crate::worker_pool::{CompressedBlock, PooledEntries, WorkerPool};
+#[cfg(feature = "worker-pool")]
+use crate::block_deflate;
+
+/// number of file lookups in flight when collecting sizes before compressing
+const DEFAULT_LOOKUP_CONCURRENCY: usize = 4;
+
+const DATA_DESCRIPTOR_SIZE: u64 = 4 + 4 + 8 + 8;
+
+/// zip64 data descriptor following the data of each file
+fn data_descriptor(crc32: u32, compressed_size: u64, uncompressed_size: u64) -> Vec<u8> {
+  let mut data_descriptor_buffer = Vec::<u8>::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
+  data_descriptor_buffer.extend_from_slice(&0x08074b50_u32.to_le_bytes());
+  data_descriptor_buffer.extend_from_slice(&crc32.to_le_bytes());
+  data_descriptor_buffer.extend_from_slice(&compressed_size.to_le_bytes());
+  data_descriptor_buffer.extend_from_slice(&uncompressed_size.to_le_bytes());
+  data_descriptor_buffer
+}
+
+/// Buffers a run takes from the pool, given back whether it succeeds or fails.
+struct RunBuffers<'a> {
+  read: Vec<u8>,
+  /// one is being filled while the other one is compressed
+  idle: Vec<js_sys::ArrayBuffer>,
+  tail: Vec<u8>,
+  codecs: Codecs,
+  transform_writer: TransformWriter<'a>,
+}
+
+impl RunBuffers<'_> {
+  fn give_back(self, buffer_pool: &BufferPool) {
+    buffer_pool.give_vec(self.read);
+    for array_buffer in self.idle {
+      buffer_pool.give_array_buffer(array_buffer);
+    }
+    buffer_pool.give_vec(self.tail);
+    for buffer in self.codecs.into_buffers() {
+      buffer_pool.give_vec(buffer);
+    }
+    buffer_pool.give_vec(self.transform_writer.into_result_buffer());
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+  /// until the writer is created, this includes looking up missing file sizes
+  Scanning,
+  Compressing,
+  Paused,
+  Done,
+  Failed,
+  Cancelled,
+}
+
+impl JobStatus {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      JobStatus::Scanning => "scanning",
+      JobStatus::Compressing => "compressing",
+      JobStatus::Paused => "paused",
+      JobStatus::Done => "done",
+      JobStatus::Failed => "failed",
+      JobStatus::Cancelled => "cancelled",
+    }
+  }
+}
+
+/// One entry written into the zip file.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone)]
+pub struct EntrySummary {
+  /// entry name inside the zip file, directories end with a slash
+  pub name: String,
+  /// always `0` for directories
+  pub crc32: u32,
+  /// unit: byte
+  pub compressed_size: f64,
+  /// unit: byte
+  pub uncompressed_size: f64,
+  /// `8` (deflate) or `0` (store, and every directory)
+  pub method: u16,
+  /// "flate2", "native", "zopfli" or "store"
+  pub codec: String,
+  /// `0` when stored or deflated by "native", which picks its own level
+  pub level: u32,
+}
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone)]
+pub struct JobSummary {
+  pub entries_written: u32,
+  /// sum of compressed entry data, headers excluded, unit: byte
+  pub compressed_bytes: f64,
+  /// unit: byte
+  pub uncompressed_bytes: f64,
+  /// `compressed_bytes / uncompressed_bytes`, `1` if there is nothing to compress
+  pub ratio: f64,
+  /// from start to finish, including time spent in pause, unit: ms
+  pub duration: f64,
+  /// "flate2", "native", "zopfli" or "store", the codec actually used for entries no rule matched
+  pub codec: String,
+  central_directory: Rc<CentralDirectory>,
+}
+
+#[wasm_bindgen]
+pub struct ArchiveJob {
+  context: Rc<Context>,
+
+  /// unit: ms
+  speed_report_interval: f64,
+
+  create_writer: js_sys::Function,
+
+  /// taken when the job starts, a job runs only once
+  file_path_list: RefCell<Option<Vec<FilePath>>>,
+  /// `None` if every file was given by `Handles::scan_files`
+  file_system: Option<web_sys::FileSystem>,
+  layout: LayoutOptions,
+
+  pause_gate: PauseGate,
+  #[cfg(feature = "worker-pool")]
+  worker_pool: Option<Rc<WorkerPool>>,
+  status: Cell<JobStatus>,
+  summary: RefCell<Option<JobSummary>>,
+
+  compress_progress: Option<js_sys::Function>,
+  byte_progress: Option<js_sys::Function>,
+  eta: Option<js_sys::Function>,
+  average_speed: Option<js_sys::Function>,
+  current_speed: Option<js_sys::Function>,
+  current_file: Option<js_sys::Function>,
+  state_change: Option<js_sys::Function>,
+}
+
+/// What a job copies from `Handles` when it is created.
+pub struct JobSnapshot {
+  pub speed_report_interval: f64,
+  pub create_writer: js_sys::Function,
+  pub file_path_list: Vec<FilePath>,
+  pub file_system: Option<web_sys::FileSystem>,
+  pub layout: LayoutOptions,
+  pub pause_gate: PauseGate,
+  #[cfg(feature = "worker-pool")]
+  pub worker_pool: Option<Rc<WorkerPool>>,
+  pub compress_progress: Option<js_sys::Function>,
+  pub byte_progress: Option<js_sys::Function>,
+  pub eta: Option<js_sys::Function>,
+  pub average_speed: Option<js_sys::Function>,
+  pub current_speed: Option<js_sys::Function>,
+  pub current_file: Option<js_sys::Function>,
+  pub state_change: Option<js_sys::Function>,
+}
+
+impl ArchiveJob {
+  pub fn new(context: &Rc<Context>, snapshot: JobSnapshot) -> Self {
+    Self {
+      context: Rc::clone(context),
+      speed_report_interval: snapshot.speed_report_interval,
+      create_writer: snapshot.create_writer,
+      file_path_list: RefCell::new(Some(snapshot.file_path_list)),
+      file_system: snapshot.file_system,
+      layout: snapshot.layout,
+      pause_gate: snapshot.pause_gate,
+      #[cfg(feature = "worker-pool")]
+      worker_pool: snapshot.worker_pool,
+      status: Cell::new(JobStatus::Scanning),
+      summary: RefCell::new(None),
+      compress_progress: snapshot.compress_progress,
+      byte_progress: snapshot.byte_progress,
+      eta: snapshot.eta,
+      average_speed: snapshot.average_speed,
+      current_speed: snapshot.current_speed,
+      current_file: snapshot.current_file,
+      state_change: snapshot.state_change,
+    }
+  }
+
+  /// Run the job, the status follows the outcome.
+  pub async fn compress_internal(&self, options: CompressOptions) -> Result<JobSummary, WasmError> {
+    let Some(file_path_list) = self.file_path_list.take() else {
+      return Err(WasmError::job_already_started(utils::type_name(&Self::compress_internal)));
+    };
+
+    let signal = options.signal.clone();
+    let result = self.run(file_path_list, options).await;
+
+    self.status.set(match &result {
+      Ok(_) => JobStatus::Done,
+      Err(_) if signal.as_ref().is_some_and(|signal| signal.aborted()) => JobStatus::Cancelled,
+      Err(_) => JobStatus::Failed,
+    });
+    if let Ok(summary) = &result {
+      self.summary.replace(Some(summary.clone()));
+    }
+
+    result
+  }
+
+  /// Estimate compressing the plan snapshot with `options` from samples of a spread of files, no writer is created.
+  pub async fn estimate_internal(&self, options: CompressOptions) -> Result<Estimate, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::estimate_internal);
+
+    let Some(mut file_path_list) = self.file_path_list.take() else {
+      return Err(WasmError::job_already_started(LOCATION));
+    };
+    let CompressOptions { compression_level, signal, memory_budget, max_bytes_per_second, codec, auto_level, ultra_iterations, rules, .. } = options;
+    let chunk_size = memory_budget.chunk_size;
+    let buffer_pool = &self.context.buffer_pool;
+    let performance = &self.context.performance;
+
+    if compression_level > codec::MAX_COMPRESSION_LEVEL {
+      return Err(WasmError::invalid_compression_level(LOCATION, &format!("{}", compression_level)));
+    }
+
+    if file_path_list.is_empty() {
+      return Err(WasmError::empty_file_list(LOCATION));
+    }
+
+    // samples are spread by size
+    self.collect_sizes(&mut file_path_list, signal.as_ref()).await?;
+
+    let entries: Vec<(&FilePath, String)> = file_path_list.iter()
+      .zip(self.layout.resolve(&file_path_list))
+      .filter_map(|(file_path, entry_name)| entry_name.map(|entry_name| {
+        (file_path, if file_path.is_dir { entry_name + "/" } else { entry_name })
+      }))
+      .collect();
+    let header_bytes = estimate::header_bytes(entries.iter().map(|(_, entry_name)| entry_name.as_str()));
+    let files: Vec<&(&FilePath, String)> = entries.iter().filter(|(file_path, _)| !file_path.is_dir).collect();
+    let sizes: Vec<u64> = files.iter().map(|(file_path, _)| file_path.size.unwrap_or(0)).collect();
+
+    let picked = estimate::pick_files(&sizes, MAX_SAMPLED_FILES);
+    let sample_size = (MAX_SAMPLE_BYTES / picked.len().max(1) as u64).max(MIN_SAMPLE_PER_FILE);
+
+    let mut codecs = Codecs::new(ultra_iterations, buffer_pool.take_vec(memory_budget.output_size));
+    // the codec buffers are given back whether sampling succeeds or fails
+    let sampled: Result<Estimator, WasmError> = async {
+      let mut auto_level = auto_level.map(AutoLevel::new);
+      let mut estimator = Estimator::default();
+      for index in picked {
+        utils::check_signal(signal.as_ref(), LOCATION)?;
+        let (file_path, entry_name) = files[index];
+        let file_size = sizes[index];
+
+        let open_start_time = performance.now();
+        let file = file_path.open(self.file_system.as_ref(), LOCATION).await?;
+        let read_start_time = performance.now();
+        let (offset, length) = estimate::sample_window(file_size, sample_size);
+        let sample = estimate::read_sample(&file, offset, length).await?;
+        let head = if rules.needs_head() && offset > 0 {
+          estimate::read_sample(&file, 0, file_size.min(SNIFF_LENGTH as u64)).await?
+        } else {
+          sample[..sample.len().min(SNIFF_LENGTH)].to_vec()
+        };
+        let read_time = performance.now() - read_start_time;
+
+        // the same choice as the job, the auto level samples the first few MB once
+        let level = match auto_level.as_mut() {
+          Some(auto_level) => {
+            auto_level.sample(&sample, || performance.now());
+            auto_level.level()
+          },
+          None => compression_level,
+        };
+        let (entry_codec_kind, entry_level) = rules.codec_and_level(entry_name, file_size, &head, codec, level);
+
+        let compress_start_time = performance.now();
+        let entry_codec = codecs.get(entry_codec_kind, entry_level);
+        let mut compressed_size = 0_u64;
+        for piece in sample.chunks(chunk_size) {
+          compressed_size += entry_codec.compress(piece).await?.len() as u64;
+        }
+        compressed_size += entry_codec.finish().await?.len() as u64;
+        let compress_time = performance.now() - compress_start_time;
+
+        estimator.push(length, compressed_size, read_start_time - open_start_time, read_time + compress_time);
+      }
+
+      Ok(estimator)
+    }.await;
+
+    for buffer in codecs.into_buffers() {
+      buffer_pool.give_vec(buffer);
+    }
+    let estimator = sampled?;
+
+    Ok(estimator.finish(files.len(), sizes.iter().sum(), header_bytes, max_bytes_per_second))
+  }
+
+  /// Look up the size of files whose size was not collected during scan.
+  async fn collect_sizes(&self, file_path_list: &mut [FilePath], signal: Option<&web_sys::AbortSignal>) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::collect_sizes);
+
+    let unknown: Vec<(usize, FilePath)> = file_path_list.iter()
+      .enumerate()
+      .filter(|(_, file_path)| !file_path.is_dir && file_path.size.is_none())
+      .map(|(index, file_path)| (index, file_path.clone()))
+      .collect();
+
+    let semaphore = &Semaphore::new(DEFAULT_LOOKUP_CONCURRENCY);
+    let file_system = self.file_system.as_ref();
+    let sizes = futures::future::try_join_all(unknown.iter().map(|(_, file_path)| async move {
+      let _permit = semaphore.acquire().await;
+      utils::check_signal(signal, LOCATION)?;
+      Ok(file_path.open(file_system, LOCATION).await?.size() as u64)
+    })).await?;
+
+    for ((index, _), size) in unknown.into_iter().zip(sizes) {
+      file_path_list[index].size = Some(size);
+    }
+    Ok(())
+  }
+
+  async fn run(&self, mut file_path_list: Vec<FilePath>, options: CompressOptions) -> Result<JobSummary, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::run);
+
+    let MemoryBudget { chunk_size, output_size, write_threshold, result_buffer_size, high_water_mark, .. } = options.memory_budget;
+    let buffer_pool = &self.context.buffer_pool;
+
+    let start_time = self.context.performance.now();
+
+    if options.compression_level > codec::MAX_COMPRESSION_LEVEL {
+      return Err(WasmError::invalid_compression_level(LOCATION, &format!("{}", options.compression_level)));
+    }
+
+    if file_path_list.is_empty() {
+      return Err(WasmError::empty_file_list(LOCATION));
+    }
+
+    // byte based progress needs the size of every file before starting
+    if self.byte_progress.is_some() || self.eta.is_some() {
+      self.collect_sizes(&mut file_path_list, options.signal.as_ref()).await?;
+    }
+
+    let mut output_file_name = options.output_file_name.clone();
+    if !output_file_name.ends_with(".zip") {
+      output_file_name += ".zip";
+    }
+    let writer = match self.create_writer.call1(&JsValue::NULL, &JsValue::from_str(&output_file_name)) {
+      Ok(w) => w,
+      Err(e) => return Err(WasmError::fail_to_create_writer(LOCATION, &format!("{:?}", e))),
+    };
+    let Ok(writer) = writer.dyn_into::<web_sys::WritableStreamDefaultWriter>() else {
+      return Err(WasmError::dynamic_cast_error(LOCATION, "JsValue", "WritableStreamDefaultWriter"));
+    };
+
+    self.status.set(JobStatus::Compressing);
+
+    // buffers left by earlier jobs are reused, `MemoryBudget::job_bytes` adds them up
+    let mut buffer_read = buffer_pool.take_vec(chunk_size);
+    buffer_read.resize(chunk_size, 0_u8);
+    let mut buffers = RunBuffers {
+      read: buffer_read,
+      idle: (0..READ_BUFFERS).map(|_| buffer_pool.take_array_buffer(chunk_size)).collect(),
+      tail: buffer_pool.take_vec(output_size),
+      codecs: Codecs::new(options.ultra_iterations, buffer_pool.take_vec(output_size)),
+      transform_writer: TransformWriter::new(
+        &writer,
+        buffer_pool,
+        write_threshold,
+        8,
+        buffer_pool.take_vec(result_buffer_size),
+        options.transform_script.is_none(),
+        OutputLimits { high_water_mark: options.high_water_mark.unwrap_or(high_water_mark), max_bytes_per_second: options.max_bytes_per_second },
+      ),
+    };
+
+    let result = self.write_archive(file_path_list, options, &writer, &mut buffers, start_time).await;
+    buffers.give_back(buffer_pool);
+
+    // whatever failed, the output is errored rather than left looking like a complete zip file
+    if let Err(e) = &result {
+      let _ = utils::await_promise(writer.abort_with_reason(&JsValue::from_str(&format!("{:?}", e)))).await;
+    }
+    result
+  }
+
+  /// Write every entry and the central directory, `run` owns the writer and the buffers.
+  async fn write_archive(&self, file_path_list: Vec<FilePath>, options: CompressOptions, writer: &web_sys::WritableStreamDefaultWriter, buffers: &mut RunBuffers<'_>, start_time: f64) -> Result<JobSummary, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::write_archive);
+
+    let CompressOptions { compression_level, signal, speed_report_interval, memory_budget, read_ahead, codec, auto_level, rules, .. } = options;
+    let speed_report_interval = speed_report_interval.unwrap_or(self.speed_report_interval);
+    let MemoryBudget { chunk_size, central_directory_chunk_size, .. } = memory_budget;
+    let RunBuffers { read: buffer_read, idle: idle_buffers, tail: tail_buffer, codecs, transform_writer } = buffers;
+    let buffer_pool = &self.context.buffer_pool;
+    let file_system = self.file_system.as_ref();
+
+    let mut bytes_written = 0_u64;
+
+    let entry_names = self.layout.resolve(&file_path_list);
+
+    let number_of_entries = entry_names.iter().flatten().count();
+    let mut central_directory = CentralDirectory::with_capacity(number_of_entries);
+    let mut total_compressed_bytes = 0_u64;
+
+    // unit: byte, uncompressed
+    let total_bytes: u64 = file_path_list.iter()
+      .zip(entry_names.iter())
+      .filter(|(file_path, entry_name)| !file_path.is_dir && entry_name.is_some())
+      .map(|(file_path, _)| file_path.size.unwrap_or(0))
+      .sum();
+    let mut total_bytes_read = 0_u64;
+
+    let mut buffer_header = Vec::<u8>::with_capacity(HEADER_BUFFER_SIZE);
+
+    let mut crc32_hasher = Hasher::new();
+
+    self.report_compress_progress(central_directory.len(), number_of_entries)?;
+
+    let mut speed_report_start_time = self.context.performance.now();
+    let mut speed_report_last_time = speed_report_start_time;
+    let mut speed_report_current_time;
+    let mut speed_report_delta_time;
+    let mut speed_report_last_total_bytes = 0;
+    let mut speed_report_current_total_bytes;
+    let mut speed_report_delta_total_bytes;
+
+    // indices of the files to write, in plan order
+    let upcoming: VecDeque<usize> = file_path_list.iter()
+      .zip(entry_names.iter())
+      .enumerate()
+      .filter(|(_, (file_path, entry_name))| !file_path.is_dir && entry_name.is_some())
+      .map(|(index, _)| index)
+      .collect();
+
+    // files are compressed by the worker pool in blocks sized from the memory budget, ahead of their turn,
+    // unless they are stored, deflated with Zopfli, or compression rules or the auto level apply
+    #[cfg(feature = "worker-pool")]
+    let use_worker_pool = codec != CodecKind::Store && compression_level < codec::ULTRA_COMPRESSION_LEVEL && rules.is_empty() && auto_level.is_none();
+    #[cfg(feature = "worker-pool")]
+    let mut pooled_entries = self.worker_pool.as_ref().filter(|_| use_worker_pool).map(|worker_pool| {
+      PooledEntries::new(Rc::clone(worker_pool), upcoming.clone(), compression_level, &memory_budget)
+    });
+
+    // otherwise the next files are looked up, and the next one starts reading, while the current one is compressed
+    let mut read_ahead = ReadAhead::new(read_ahead, file_system.cloned(), Rc::clone(buffer_pool), upcoming);
+
+    // the worker pool always deflates with flate2
+    #[cfg(feature = "worker-pool")]
+    let codec = if pooled_entries.is_some() { CodecKind::Flate2 } else { codec };
+    // the codec of entries no rule matches
+    let default_codec_kind = codecs.get(codec, compression_level).kind();
+    // with `level: "auto"`, the level of entries no rule gives one moves between files
+    let mut auto_level = auto_level.map(AutoLevel::new);
+
+    for (file_path, entry_name) in file_path_list.iter().zip(entry_names) { // start of file loop
+      let FilePath { path, is_dir, .. } = file_path;
+
+      let Some(mut zip_path) = entry_name else {
+        continue;
+      };
+
+      // nothing is half written here, time spent in pause does not count for speed measurement
+      let paused_time = self.wait_if_paused(signal.as_ref()).await?;
+      speed_report_start_time += paused_time;
+      speed_report_last_time += paused_time;
+      let mut entry_start_time = self.context.performance.now();
+
+      utils::check_signal_and_abort_writer(signal.as_ref(), writer, LOCATION).await?;
+
+      self.report_current_file(path)?;
+
+      // a file is opened, and its first chunk read, before its header is written, as rules may look at its content
+      #[cfg(feature = "worker-pool")]
+      let is_pooled = pooled_entries.is_some();
+      #[cfg(not(feature = "worker-pool"))]
+      let is_pooled = false;
+      let opened_file = if *is_dir || is_pooled {
+        None
+      } else {
+        let OpenedFile { file_size, reader, first_read } = read_ahead.next(&file_path_list, idle_buffers, chunk_size).await?;
+        Some((file_size, reader, read_ahead::wait_read(first_read).await?))
+      };
+
+      // pick codec and level, the auto level samples the first chunks of the job
+      let compression_level = match (auto_level.as_mut(), opened_file.as_ref()) {
+        (Some(auto_level), Some((_, _, first_read_result))) => {
+          if auto_level.is_sampling() {
+            let view = &first_read_result.view;
+            let sample = view.subarray(0, view.length().min(MAX_SAMPLE_PER_FILE as u32)).to_vec();
+            auto_level.sample(&sample, || self.context.performance.now());
+          }
+          auto_level.level()
+        },
+        (Some(auto_level), None) => auto_level.level(),
+        (None, _) => compression_level,
+      };
+      let (entry_codec_kind, entry_level) = match opened_file.as_ref() {
+        Some((file_size, _, first_read_result)) if !rules.is_empty() => {
+          let head = if rules.needs_head() {
+            let view = &first_read_result.view;
+            view.subarray(0, view.length().min(SNIFF_LENGTH as u32)).to_vec()
+          } else {
+            Vec::new()
+          };
+          rules.codec_and_level(&zip_path, *file_size, &head, codec, compression_level)
+        },
+        _ => (codec, compression_level),
+      };
+      let entry_codec = match codecs.get(entry_codec_kind, entry_level).kind() {
+        CodecKind::Store => EntryCodec::STORED,
+        // the browser picks its own level
+        CodecKind::Native => EntryCodec { kind: CodecKind::Native, level: 0 },
+        kind => EntryCodec { kind, level: entry_level as u8 },
+      };
+
+      // create file header
+      if *is_dir {
+        zip_path.push('/');
+      }
+      let lfh_pos = bytes_written;
+      let mut file_header = appnote63::FileHeader::new(zip_path.clone(), lfh_pos, *is_dir);
+      if !*is_dir {
+        file_header.set_compression_method(entry_codec.kind.method());
+      }
+
+      // write local file header
+      file_header.write_into_as_lfh(&mut buffer_header);
+      transform_writer.write_all(&buffer_header).unwrap();
+      bytes_written += buffer_header.len() as u64;
+
+      transform_writer.dump(true).await?;
+
+      // speed measurement
+      speed_report_current_time = self.context.performance.now();
+      speed_report_current_total_bytes = bytes_written;
+      speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+      speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
+      if speed_report_delta_time >= speed_report_interval {
+        self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
+        self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+        self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
+        speed_report_last_time = speed_report_current_time;
+        speed_report_last_total_bytes = speed_report_current_total_bytes;
+      }
+
+      if *is_dir {
+        central_directory.push(&zip_path, lfh_pos, EntryCodec::STORED, 0, 0, 0);
+        continue;
+      }
+
+      // with a worker pool, compressed blocks only need to be written in order
+      #[cfg(feature = "worker-pool")]
+      if let Some(pooled_entries) = pooled_entries.as_mut() {
+        let mut blocks = pooled_entries.next(&file_path_list, file_system).await?;
+        let file_size = blocks.file_size();
+
+        let mut crc32 = 0_u32;
+        let mut uncompressed_size = 0_u64;
+        let mut compressed_size = 0_u64;
+
+        while let Some(CompressedBlock { data, crc32: block_crc32, uncompressed_size: block_size }) = blocks.next().await? {
+          let paused_time = self.wait_if_paused(signal.as_ref()).await?;
+          speed_report_start_time += paused_time;
+          speed_report_last_time += paused_time;
+
+          utils::check_signal_and_abort_writer(signal.as_ref(), writer, LOCATION).await?;
+
+          crc32 = block_deflate::combine_crc32(crc32, block_crc32, block_size);
+          uncompressed_size += block_size;
+          total_bytes_read += block_size;
+          compressed_size += data.len() as u64;
+
+          self.report_byte_progress(total_bytes_read, total_bytes, uncompressed_size, file_size)?;
+
+          // compressed block -> transform writer
+          transform_writer.write_all(&data).unwrap();
+
+          transform_writer.dump(true).await?;
+
+          // speed measurement
+          speed_report_current_time = self.context.performance.now();
+          speed_report_current_total_bytes = bytes_written + compressed_size;
+          speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+          speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
+          if speed_report_delta_time >= speed_report_interval {
+            self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
+            self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+            self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
+            speed_report_last_time = speed_report_current_time;
+            speed_report_last_total_bytes = speed_report_current_total_bytes;
+          }
+        }
+
+        // data descriptor -> transform writer
+        transform_writer.write_all(&data_descriptor(crc32, compressed_size, uncompressed_size)).unwrap();
+
+        transform_writer.dump(true).await?;
+
+        bytes_written += compressed_size + DATA_DESCRIPTOR_SIZE;
+        total_compressed_bytes += compressed_size;
+
+        central_directory.push(&zip_path, lfh_pos, entry_codec, crc32, compressed_size, uncompressed_size);
+
+        self.report_compress_progress(central_directory.len(), number_of_entries)?;
+        continue;
+      }
+
+      // File size, BYOB reader and the first chunk
+      let Some((file_size, reader, first_read_result)) = opened_file else {
+        unreachable!("files are opened before their header is written");
+      };
+      let mut ready_read = Some(first_read_result);
+      let mut pending_read = None;
+      let codec = codecs.get(entry_codec_kind, entry_level);
+
+      let mut uncompressed_size = 0_u64;
+      let mut compressed_size = 0_u64;
+
+      // the read-compress-write loop
+      loop { // start of compress loop
+        let paused_time = self.wait_if_paused(signal.as_ref()).await?;
+        speed_report_start_time += paused_time;
+        speed_report_last_time += paused_time;
+        entry_start_time += paused_time;
+
+        utils::check_signal_and_abort_writer(signal.as_ref(), writer, LOCATION).await?;
+
+        // js reader -> js buffer
+        let ReadResult { new_buffer: array_buffer_new_read, view: view_read, done } = match ready_read.take() {
+          Some(read_result) => read_result,
+          None => match pending_read.take() {
+            Some(pending_read) => read_ahead::wait_read(pending_read).await?,
+            None => utils::byob_read(&idle_buffers.pop().unwrap_or_else(|| buffer_pool.take_array_buffer(chunk_size)), &reader).await?,
+          },
+        };
+
+        // the other buffer is filled while this one is compressed, after the last read it starts the next file
+        if done {
+          read_ahead.prefetch_next(&file_path_list, idle_buffers, chunk_size);
+        } else {
+          pending_read = Some(read_ahead::spawn_read(idle_buffers.pop().unwrap_or_else(|| buffer_pool.take_array_buffer(chunk_size)), &reader));
+        }
+
+        let bytes_read = view_read.byte_length();
+        uncompressed_size += bytes_read as u64;
+        total_bytes_read += bytes_read as u64;
+
+        self.report_byte_progress(total_bytes_read, total_bytes, uncompressed_size, file_size)?;
+
+        // if indeed read some bytes
+        if bytes_read > 0 {
+          // js buffer -> wasm buffer slice
+          let wasm_slice = &mut buffer_read[0..bytes_read as usize];
+          view_read.copy_to(wasm_slice);
+
+          // wasm buffer slice -> crc hasher
+          crc32_hasher.update(wasm_slice);
+
+          // wasm buffer slice -> codec -> compressed bytes
+          let compressed = codec.compress(wasm_slice).await?;
+
+          let bytes_output = compressed.len();
+
+          // if indeed get some compressed bytes
+          if bytes_output > 0 {
+            compressed_size += bytes_output as u64;
+
+            // compressed bytes -> transform writer
+            transform_writer.write_all(compressed).unwrap();
+
+            transform_writer.dump(true).await?;
+
+            // speed measurement
+            speed_report_current_time = self.context.performance.now();
+            speed_report_current_total_bytes = bytes_written + compressed_size;
+            speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+            speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
+            if speed_report_delta_time >= speed_report_interval {
+              self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
+              self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+              self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
+              speed_report_last_time = speed_report_current_time;
+              speed_report_last_total_bytes = speed_report_current_total_bytes;
+            }
+          }
+        }
+
+        // copied into wasm memory, the buffer can take another read
+        idle_buffers.push(array_buffer_new_read);
+
+        if done {
+          // codec -> the end of the compressed stream
+          let compressed = codec.finish().await?;
+
+          // if indeed we get some final extra compressed bytes to write
+          let bytes_output = compressed.len();
+          if bytes_output > 0 {
+            compressed_size += bytes_output as u64;
+
+            // compressed bytes -> transform writer
+            transform_writer.write_all(compressed).unwrap();
+
+            transform_writer.dump(true).await?;
+
+            // speed measurement
+            speed_report_current_time = self.context.performance.now();
+            speed_report_current_total_bytes = bytes_written + compressed_size;
+            speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+            speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
+            if speed_report_delta_time >= speed_report_interval {
+              self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
+              self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+              self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
+              speed_report_last_time = speed_report_current_time;
+              speed_report_last_total_bytes = speed_report_current_total_bytes;
+            }
+          }
+
+          break;
+        }
+      } // end of compress loop
+
+      // finalize crc32 and recreate the hasher
+      let crc32 = crc32_hasher.finalize();
+      crc32_hasher = Hasher::new();
+
+      bytes_written += compressed_size;
+      total_compressed_bytes += compressed_size;
+
+      // write data descriptor
+      let data_descriptor_buffer = data_descriptor(crc32, compressed_size, uncompressed_size);
+
+      // wasm buffer -> transform writer
+      transform_writer.write_all(&data_descriptor_buffer).unwrap();
+
+      transform_writer.dump(true).await?;
+
+      bytes_written += DATA_DESCRIPTOR_SIZE;
+
+      // speed measurement
+      speed_report_current_time = self.context.performance.now();
+      speed_report_current_total_bytes = bytes_written;
+      speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+      speed_report_delta_total_bytes = speed_report_current_total_bytes - speed_report_last_total_bytes;
+      if speed_report_delta_time >= speed_report_interval {
+        self.report_average_speed(speed_report_current_total_bytes, speed_report_current_time - speed_report_start_time)?;
+        self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+        self.report_current_speed(speed_report_delta_total_bytes, speed_report_delta_time)?;
+        speed_report_last_time = speed_report_current_time;
+        speed_report_last_total_bytes = speed_report_current_total_bytes;
+      }
+      if let Some(auto_level) = auto_level.as_mut() {
+        auto_level.record(uncompressed_size, speed_report_current_time - entry_start_time);
+      }
+
+      // only a compact record is kept until the central directory is written
+      central_directory.push(&zip_path, lfh_pos, entry_codec, crc32, compressed_size, uncompressed_size);
+
+      self.report_compress_progress(central_directory.len(), number_of_entries)?;
+    } // end of file loop
+
+    utils::check_signal_and_abort_writer(signal.as_ref(), writer, LOCATION).await?;
+
+    // write tail data
+
+    let start_of_central_directory = bytes_written;
+
+    // central directory headers, generated and sent a chunk at a time, each write waits for the writer to be ready
+    let mut size_of_central_directory = 0_u64;
+    let number_of_file_headers = central_directory.len() as u64;
+    let mut next_entry = 0;
+    loop {
+      next_entry = central_directory.write_chunk(next_entry, tail_buffer, central_directory_chunk_size);
+      size_of_central_directory += tail_buffer.len() as u64;
+      if next_entry == central_directory.len() {
+        break; // the last chunk is sent along with the end of central directory records
+      }
+      transform_writer.write_all(tail_buffer).unwrap();
+      transform_writer.dump(true).await?;
+      tail_buffer.clear();
+    }
+
+    let relative_offset_of_zip64_end_of_central_directory_record = start_of_central_directory + size_of_central_directory;
+
+    // zip64 end of central directory record
+    tail_buffer.extend_from_slice(&0x06064b50_u32.to_le_bytes()); // zip64 end of central dir signature
+    tail_buffer.extend_from_slice(&(2_u64 + 2_u64 + 4_u64 + 4_u64 + 8_u64 + 8_u64 + 8_u64 + 8_u64).to_le_bytes());
+    tail_buffer.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+    tail_buffer.extend_from_slice(&VERSION_NEEDED_TO_EXTRACT.to_le_bytes());
+    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
+    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
+    tail_buffer.extend_from_slice(&number_of_file_headers.to_le_bytes());
+    tail_buffer.extend_from_slice(&number_of_file_headers.to_le_bytes());
+    tail_buffer.extend_from_slice(&size_of_central_directory.to_le_bytes());
+    tail_buffer.extend_from_slice(&start_of_central_directory.to_le_bytes());
+
+    // zip64 end of central directory locator
+    tail_buffer.extend_from_slice(&0x07064b50_u32.to_le_bytes()); // zip64 end of central dir locator signature
+    tail_buffer.extend_from_slice(&0_u32.to_le_bytes()); // splitting is not supported
+    tail_buffer.extend_from_slice(&relative_offset_of_zip64_end_of_central_directory_record.to_le_bytes());
+    tail_buffer.extend_from_slice(&1_u32.to_le_bytes()); // splitting is not supported
+
+    // End of central directory record
+    tail_buffer.extend_from_slice(&0x06054b50_u32.to_le_bytes());
+    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, number of this disk
+    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, number of the disk with the start of the central directory
+    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, total number of entries in the central directory on this disk
+    tail_buffer.extend_from_slice(&0xFFFF_u16.to_le_bytes()); // zip64, total number of entries in the central directory
+    tail_buffer.extend_from_slice(&0xFFFFFFFF_u32.to_le_bytes()); // zip64, size of the central directory
+    tail_buffer.extend_from_slice(&0xFFFFFFFF_u32.to_le_bytes()); // zip64, offset of start of central directory with respect to the starting disk number
+    tail_buffer.extend_from_slice(&0_u16.to_le_bytes()); // zip file comment length
+    // central_directory_buffer.extend_from_slice(&[0_u8; 0]); // zip file comment (no comment)
+
+    // tail buffer -> transform buffer
+    transform_writer.write_all(tail_buffer).unwrap();
+
+    // no more data
+    transform_writer.flush().unwrap();
+
+    transform_writer.dump(false).await?;
+
+    // final speed measurement
+    speed_report_current_time = self.context.performance.now();
+    speed_report_delta_time = speed_report_current_time - speed_report_last_time;
+    self.report_average_speed(bytes_written, speed_report_current_time - speed_report_start_time)?;
+    self.report_eta(total_bytes_read, total_bytes, speed_report_current_time - speed_report_start_time)?;
+    self.report_current_speed(bytes_written - speed_report_last_total_bytes, speed_report_delta_time)?;
+
+    if let Err(e) = utils::await_promise(writer.close()).await {
+      return Err(WasmError::fail_to_close_writer(LOCATION, &format!("{:?}", e)));
+    }
+
+    Ok(JobSummary {
+      entries_written: number_of_file_headers as u32,
+      compressed_bytes: total_compressed_bytes as f64,
+      uncompressed_bytes: total_bytes_read as f64,
+      ratio: if total_bytes_read > 0 { total_compressed_bytes as f64 / total_bytes_read as f64 } else { 1_f64 },
+      duration: self.context.performance.now() - start_time,
+      codec: default_codec_kind.name().to_owned(),
+      central_directory: Rc::new(central_directory),
+    })
+  }
+
+  fn report_compress_progress(&self, number_of_compressed_files: usize, number_of_all_files: usize) -> Result<(), WasmError> {
+    if let Some(compress_progress) = self.compress_progress.as_ref() {
+      if let Err(e) = compress_progress.call2(
+        &JsValue::NULL,
+        &JsValue::from_f64(number_of_compressed_files as f64),
+        &JsValue::from_f64(number_of_all_files as f64)
+      ) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_compress_progress), "compress_progress", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  fn report_average_speed(&self, total_bytes_written: u64, total_time_elapsed: f64) -> Result<(), WasmError> {
+    if let Some(average_speed) = self.average_speed.as_ref() {
+      if let Err(e) = average_speed.call2(
+        &JsValue::NULL,
+        &JsValue::from_f64(total_bytes_written as f64),
+        &JsValue::from_f64(total_time_elapsed)
+      ) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_average_speed), "average_speed", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  fn report_current_speed(&self, delta_bytes_written: u64, delta_time_elapsed: f64) -> Result<(), WasmError> {
+    if let Some(current_speed) = self.current_speed.as_ref() {
+      if let Err(e) = current_speed.call2(
+        &JsValue::NULL,
+        &JsValue::from_f64(delta_bytes_written as f64),
+        &JsValue::from_f64(delta_time_elapsed)
+      ) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_current_speed), "current_speed", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  fn report_byte_progress(&self, total_bytes_read: u64, total_bytes: u64, current_file_bytes_read: u64, current_file_size: u64) -> Result<(), WasmError> {
+    if let Some(byte_progress) = self.byte_progress.as_ref() {
+      let arguments = js_sys::Array::of4(
+        &JsValue::from_f64(total_bytes_read as f64),
+        &JsValue::from_f64(total_bytes as f64),
+        &JsValue::from_f64(current_file_bytes_read as f64),
+        &JsValue::from_f64(current_file_size as f64),
+      );
+      if let Err(e) = byte_progress.apply(&JsValue::NULL, &arguments) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_byte_progress), "byte_progress", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  /// The estimation assumes the remaining bytes are read as fast as the bytes read so far.
+  fn report_eta(&self, total_bytes_read: u64, total_bytes: u64, total_time_elapsed: f64) -> Result<(), WasmError> {
+    if let Some(eta) = self.eta.as_ref() {
+      if total_bytes_read == 0 || total_time_elapsed <= 0_f64 {
+        return Ok(());
+      }
+      let bytes_per_ms = total_bytes_read as f64 / total_time_elapsed;
+      let remaining_time = total_bytes.saturating_sub(total_bytes_read) as f64 / bytes_per_ms;
+      if let Err(e) = eta.call2(
+        &JsValue::NULL,
+        &JsValue::from_f64(remaining_time),
+        &JsValue::from_f64(bytes_per_ms)
+      ) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_eta), "eta", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  /// Suspend at a safe point while paused, returns the time spent in pause, unit: ms.
+  async fn wait_if_paused(&self, signal: Option<&web_sys::AbortSignal>) -> Result<f64, WasmError> {
+    if !self.pause_gate.is_paused() {
+      return Ok(0_f64);
+    }
+
+    let pause_start_time = self.context.performance.now();
+    self.status.set(JobStatus::Paused);
+    self.report_state_change("paused")?;
+    self.pause_gate.wait(signal).await;
+    self.status.set(JobStatus::Compressing);
+    if !signal.is_some_and(|signal| signal.aborted()) {
+      self.report_state_change("resumed")?;
+    }
+    Ok(self.context.performance.now() - pause_start_time)
+  }
+
+  fn report_state_change(&self, state: &str) -> Result<(), WasmError> {
+    if let Some(state_change) = self.state_change.as_ref() {
+      if let Err(e) = state_change.call1(&JsValue::NULL, &JsValue::from_str(state)) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_state_change), "state_change", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  fn report_current_file(&self, path: &str) -> Result<(), WasmError> {
+    if let Some(current_file) = self.current_file.as_ref() {
+      if let Err(e) = current_file.call1(&JsValue::NULL, &JsValue::from_str(path)) {
+        return Err(WasmError::fail_to_invoke_callback(utils::type_name(&Self::report_current_file), "current_file", &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[wasm_bindgen]
+impl JobSummary {
+  /// Every entry in the same order as in the zip file, built on each call.
+  pub fn entries(&self) -> Vec<EntrySummary> {
+    self.central_directory.entries()
+      .map(|entry| EntrySummary {
+        name: entry.name.to_owned(),
+        crc32: entry.crc32,
+        compressed_size: entry.compressed_size as f64,
+        uncompressed_size: entry.uncompressed_size as f64,
+        method: entry.codec.kind.method(),
+        codec: entry.codec.kind.name().to_owned(),
+        level: entry.codec.level as u32,
+      })
+      .collect()
+  }
+}
+
+#[wasm_bindgen]
+impl ArchiveJob {
+  /// Compress the plan snapshot of this job, a job can only be run once.
+  ///
+  /// # Parameters
+  ///
+  /// * `output_file_name` - name of output zip file
+  /// * `compression_level` - an integer, minimal 0, maximum 9
+  /// * `signal` - an optional `AbortSignal`, once aborted the writer is aborted too
+  ///
+  /// # Returns
+  ///
+  /// - resolve: a `JobSummary` object
+  /// - reject: a `WasmError` object
+  pub async fn compress(&self, output_file_name: String, compression_level: u32, signal: Option<web_sys::AbortSignal>) -> Result<JobSummary, WasmError> {
+    self.compress_internal(CompressOptions::new(output_file_name, compression_level, signal)).await
+  }
+
+  /// Compress the plan snapshot of this job with a `CompressOptions` object, a job can only be run once.
+  ///
+  /// # Returns
+  ///
+  /// - resolve: a `JobSummary` object
+  /// - reject: a `WasmError` object
+  pub async fn compress_with_options(&self, options: CompressOptionsObject) -> Result<JobSummary, WasmError> {
+    self.compress_internal(CompressOptions::parse(&options)?).await
+  }
+
+  /// One of `"scanning"`, `"compressing"`, `"paused"`, `"done"`, `"failed"` and `"cancelled"`.
+  pub fn status(&self) -> String {
+    self.status.get().as_str().to_owned()
+  }
+
+  /// The summary of a finished job, `undefined` unless the status is `"done"`.
+  pub fn summary(&self) -> Option<JobSummary> {
+    self.summary.borrow().clone()
+  }
+
+  /// Suspend this job at its next safe point, buffers and compression state are kept.
+  pub fn pause(&self) {
+    self.pause_gate.pause();
+  }
+
+  /// Continue this job if paused.
+  pub fn resume(&self) {
+    self.pause_gate.resume();
+  }
+}
@@ -0,0 +1,295 @@
+//! The type `LayoutOptions` decides how archive paths of the plan are turned into entry names inside the zip file.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::context::FilePath;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct LayoutOptions {
+  root_folder: Option<String>,
+  strip_components: u32,
+  flatten: bool,
+  directory_entries: bool,
+  keep_empty_directories: bool,
+}
+
+impl Default for LayoutOptions {
+  fn default() -> Self {
+    Self {
+      root_folder: None,
+      strip_components: 0,
+      flatten: false,
+      directory_entries: true,
+      keep_empty_directories: true,
+    }
+  }
+}
+
+#[wasm_bindgen]
+impl LayoutOptions {
+  /// Create options which store every entry under its archive path, like `Handles::compress` does by default.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Put every entry under a top level folder, like `export-2026-10-17`, an empty string removes the folder.
+  pub fn set_root_folder(&mut self, root_folder: &str) -> Result<(), WasmError> {
+    if root_folder.trim_matches('/').is_empty() {
+      self.root_folder = None;
+      return Ok(());
+    }
+    match utils::normalize_archive_path(root_folder) {
+      Some(root_folder) => self.root_folder = Some(root_folder),
+      None => return Err(WasmError::invalid_archive_path(utils::type_name(&Self::set_root_folder), root_folder)),
+    }
+    Ok(())
+  }
+
+  /// Remove this many leading components from every archive path, entries with nothing left are dropped.
+  pub fn set_strip_components(&mut self, strip_components: u32) {
+    self.strip_components = strip_components;
+  }
+
+  /// Store every file directly under the root, names which are already taken get a ` (n)` suffix.
+  pub fn set_flatten(&mut self, flatten: bool) {
+    self.flatten = flatten;
+  }
+
+  /// Whether directories get entries of their own, `true` by default.
+  pub fn set_directory_entries(&mut self, directory_entries: bool) {
+    self.directory_entries = directory_entries;
+  }
+
+  /// Whether directories without any file under them are kept, `true` by default.
+  pub fn set_keep_empty_directories(&mut self, keep_empty_directories: bool) {
+    self.keep_empty_directories = keep_empty_directories;
+  }
+}
+
+impl LayoutOptions {
+  /// Resolve the entry name of every plan entry, without trailing slash, `None` if the entry is dropped.
+  pub fn resolve(&self, file_path_list: &[FilePath]) -> Vec<Option<String>> {
+    let mut resolved: Vec<Option<String>> = file_path_list.iter()
+      .map(|file_path| {
+        if file_path.is_dir && (self.flatten || !self.directory_entries) {
+          return None;
+        }
+
+        let components: Vec<&str> = file_path.archive_path.split('/').collect();
+        if components.len() <= self.strip_components as usize {
+          return None;
+        }
+        let stripped = &components[self.strip_components as usize..];
+
+        let name = if self.flatten {
+          stripped[stripped.len() - 1].to_owned()
+        } else {
+          stripped.join("/")
+        };
+
+        Some(match &self.root_folder {
+          Some(root_folder) => format!("{}/{}", root_folder, name),
+          None => name,
+        })
+      })
+      .collect();
+
+    // duplicated directories are merged, duplicated files are renamed, and so are directories whose name a file took
+    let mut names = Names::default();
+    for (index, name) in resolved.iter_mut().enumerate() {
+      let Some(current) = name.take() else {
+        continue;
+      };
+      let current = names.place_parents(&current);
+      *name = if file_path_list[index].is_dir {
+        let directory = names.place_directory(current);
+        names.written_directories.insert(directory.clone()).then_some(directory)
+      } else {
+        Some(names.place_file(current))
+      };
+    }
+
+    if !self.keep_empty_directories {
+      let file_names: Vec<&String> = resolved.iter()
+        .zip(file_path_list)
+        .filter(|(_, file_path)| !file_path.is_dir)
+        .filter_map(|(name, _)| name.as_ref())
+        .collect();
+      let mut non_empty = HashSet::new();
+      for file_name in file_names {
+        let mut end = file_name.len();
+        while let Some(position) = file_name[..end].rfind('/') {
+          if !non_empty.insert(file_name[..position].to_owned()) {
+            break;
+          }
+          end = position;
+        }
+      }
+      for (name, file_path) in resolved.iter_mut().zip(file_path_list) {
+        if file_path.is_dir && name.as_ref().is_some_and(|name| !non_empty.contains(name)) {
+          *name = None;
+        }
+      }
+    }
+
+    resolved
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Taken {
+  File,
+  /// with or without an entry of its own
+  Directory,
+}
+
+/// Entry names given so far.
+#[derive(Default)]
+struct Names {
+  taken: HashMap<String, Taken>,
+  /// directories renamed because a file had their name first, everything under them follows
+  renamed: HashMap<String, String>,
+  written_directories: HashSet<String>,
+}
+
+impl Names {
+  /// Place every directory leading to `name`, returns `name` under its placed parent.
+  fn place_parents(&mut self, name: &str) -> String {
+    let Some((parent, file_name)) = name.rsplit_once('/') else {
+      return name.to_owned();
+    };
+    let mut placed = String::with_capacity(name.len());
+    for component in parent.split('/') {
+      let directory = if placed.is_empty() { component.to_owned() } else { format!("{}/{}", placed, component) };
+      placed = self.place_directory(directory);
+    }
+    format!("{}/{}", placed, file_name)
+  }
+
+  /// A directory shares the name of another one, but gets ` (n)` if a file has it.
+  fn place_directory(&mut self, directory: String) -> String {
+    if let Some(renamed) = self.renamed.get(&directory) {
+      return renamed.clone();
+    }
+    match self.taken.get(&directory) {
+      Some(Taken::Directory) => directory,
+      Some(Taken::File) => {
+        let unique = unique_name(&directory, false, |candidate| self.taken.contains_key(candidate));
+        self.taken.insert(unique.clone(), Taken::Directory);
+        self.renamed.insert(directory, unique.clone());
+        unique
+      },
+      None => {
+        self.taken.insert(directory.clone(), Taken::Directory);
+        directory
+      },
+    }
+  }
+
+  /// A file gets ` (n)` if its name is taken by anything.
+  fn place_file(&mut self, file: String) -> String {
+    let file = if self.taken.contains_key(&file) {
+      unique_name(&file, true, |candidate| self.taken.contains_key(candidate))
+    } else {
+      file
+    };
+    self.taken.insert(file.clone(), Taken::File);
+    file
+  }
+}
+
+/// `a/b.txt` -> `a/b (1).txt`, `a/b (2).txt`, ..., the suffix goes before the extension only if `keep_extension`.
+fn unique_name(name: &str, keep_extension: bool, is_taken: impl Fn(&str) -> bool) -> String {
+  let file_name_start = name.rfind('/').map_or(0, |position| position + 1);
+  let (stem, extension) = match name[file_name_start..].rfind('.') {
+    Some(position) if position > 0 && keep_extension => name.split_at(file_name_start + position),
+    _ => (name, ""),
+  };
+  (1..)
+    .map(|n| format!("{} ({}){}", stem, n, extension))
+    .find(|candidate| !is_taken(candidate))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn plan(entries: &[&str]) -> Vec<FilePath> {
+    entries.iter()
+      .map(|entry| FilePath {
+        path: format!("/{}", entry.trim_end_matches('/')),
+        is_dir: entry.ends_with('/'),
+        size: None,
+        last_modified: None,
+        archive_path: entry.trim_end_matches('/').to_owned(),
+        file: None,
+      })
+      .collect()
+  }
+
+  fn resolve(layout: &LayoutOptions, entries: &[&str]) -> Vec<Option<String>> {
+    layout.resolve(&plan(entries))
+  }
+
+  fn names(names: &[Option<&str>]) -> Vec<Option<String>> {
+    names.iter().map(|name| name.map(str::to_owned)).collect()
+  }
+
+  #[test]
+  fn strip_components_and_root_folder() {
+    let mut layout = LayoutOptions::new();
+    layout.set_strip_components(1);
+    layout.set_root_folder("/export/").unwrap();
+    assert_eq!(
+      resolve(&layout, &["top/", "top/a/", "top/a/b.txt", "c.txt"]),
+      names(&[None, Some("export/a"), Some("export/a/b.txt"), None]),
+    );
+    assert!(layout.set_root_folder("a/../b").is_err());
+  }
+
+  #[test]
+  fn flattened_duplicates_are_suffixed() {
+    let mut layout = LayoutOptions::new();
+    layout.set_flatten(true);
+    assert_eq!(
+      resolve(&layout, &["a/", "a/x.txt", "b/", "b/x.txt", "c/x.txt", "c/.env", "d/.env"]),
+      names(&[None, Some("x.txt"), None, Some("x (1).txt"), Some("x (2).txt"), Some(".env"), Some(".env (1)")]),
+    );
+  }
+
+  #[test]
+  fn directories_merge_but_do_not_take_a_file_name() {
+    let mut layout = LayoutOptions::new();
+    layout.set_strip_components(1);
+    assert_eq!(
+      resolve(&layout, &["p/a/", "p/a/x", "q/a/", "q/a/y", "r/a.d", "s/a.d/", "s/a.d/z"]),
+      names(&[Some("a"), Some("a/x"), None, Some("a/y"), Some("a.d"), Some("a.d (1)"), Some("a.d (1)/z")]),
+    );
+
+    // without directory entries, a file still can not share the name of an implied directory
+    layout.set_directory_entries(false);
+    assert_eq!(
+      resolve(&layout, &["p/a/x", "q/a", "r/a/y"]),
+      names(&[Some("a/x"), Some("a (1)"), Some("a/y")]),
+    );
+    assert_eq!(
+      resolve(&layout, &["p/a", "q/a/x"]),
+      names(&[Some("a"), Some("a (1)/x")]),
+    );
+  }
+
+  #[test]
+  fn empty_directories_can_be_left_out() {
+    let mut layout = LayoutOptions::new();
+    layout.set_keep_empty_directories(false);
+    assert_eq!(
+      resolve(&layout, &["a/", "a/b/", "a/b/c.txt", "a/e/", "f/"]),
+      names(&[Some("a"), Some("a/b"), Some("a/b/c.txt"), None, None]),
+    );
+  }
+}
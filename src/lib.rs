@@ -13,6 +13,29 @@ mod wasm_error;
 mod handles;
 mod transform_writer;
 mod recover_writer;
+mod glob;
+mod scan_options;
+mod ignore_rules;
+mod plan;
+mod layout;
+mod semaphore;
+mod scan_report;
+mod pause_gate;
+mod job;
+mod operation_state;
+mod compress_options;
+mod buffer_pool;
+mod central_directory;
+mod read_ahead;
+mod output_limits;
+mod codec;
+mod compress_rules;
+mod auto_level;
+mod estimate;
+#[cfg(feature = "worker-pool")]
+mod worker_pool;
+#[cfg(any(feature = "worker-pool", test))]
+mod block_deflate;
 
 #[wasm_bindgen]
 pub fn initialize_context(create_writer: js_sys::Function) -> Handles {
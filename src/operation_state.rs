@@ -0,0 +1,160 @@
+//! The type `OperationState` tracks what `Handles` is doing, so that overlapping operations are rejected with a
+//! `WasmError` instead of trapping on a `RefCell` which is already borrowed.
+//!
+//! Scan, transform and recover own the plan while running, nothing else touching the plan may start meanwhile.
+//! Compress only reads the plan when it starts, so any number of compress jobs may run along with other operations,
+//! as long as no scan is replacing the plan at that moment.
+
+use std::cell::Cell;
+
+use crate::wasm_error::WasmError;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+  Scan,
+  Compress,
+  Transform,
+  Recover,
+  EditPlan,
+}
+
+impl Operation {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Operation::Scan => "scan",
+      Operation::Compress => "compress",
+      Operation::Transform => "transform",
+      Operation::Recover => "recover",
+      Operation::EditPlan => "edit_plan",
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct OperationState {
+  /// the running scan, transform or recover
+  exclusive: Cell<Option<Operation>>,
+  /// number of running compress jobs started by `Handles`
+  compress_jobs: Cell<u32>,
+}
+
+/// Ends its operation when dropped, including early returns on error.
+pub struct OperationGuard<'a> {
+  state: &'a OperationState,
+  operation: Operation,
+}
+
+impl OperationState {
+  /// Whether `operation` may start now.
+  pub fn check(&self, operation: Operation, location: &str) -> Result<(), WasmError> {
+    let Some(running) = self.exclusive.get() else {
+      return Ok(());
+    };
+    if operation == Operation::Compress && running != Operation::Scan {
+      return Ok(());
+    }
+    Err(WasmError::operation_in_progress(location, operation.as_str(), running.as_str()))
+  }
+
+  /// Start `operation`, it lasts until the returned guard is dropped.
+  pub fn begin(&self, operation: Operation, location: &str) -> Result<OperationGuard<'_>, WasmError> {
+    self.check(operation, location)?;
+    match operation {
+      Operation::Compress => self.compress_jobs.set(self.compress_jobs.get() + 1),
+      Operation::EditPlan => {},
+      _ => self.exclusive.set(Some(operation)),
+    }
+    Ok(OperationGuard { state: self, operation })
+  }
+
+  /// Whether a compress job started by `Handles` is running.
+  pub fn is_compressing(&self) -> bool {
+    self.compress_jobs.get() > 0
+  }
+
+  /// `"idle"`, `"scanning"`, `"transforming"`, `"recovering"` or `"compressing"`, the latter only if nothing else is running.
+  pub fn current(&self) -> &'static str {
+    match self.exclusive.get() {
+      Some(Operation::Scan) => "scanning",
+      Some(Operation::Transform) => "transforming",
+      Some(Operation::Recover) => "recovering",
+      _ if self.is_compressing() => "compressing",
+      _ => "idle",
+    }
+  }
+}
+
+impl Drop for OperationGuard<'_> {
+  fn drop(&mut self) {
+    match self.operation {
+      Operation::Compress => self.state.compress_jobs.set(self.state.compress_jobs.get() - 1),
+      Operation::EditPlan => {},
+      _ => self.state.exclusive.set(None),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EXCLUSIVE: [Operation; 3] = [Operation::Scan, Operation::Transform, Operation::Recover];
+
+  fn assert_rejected(state: &OperationState, operation: Operation, running: Operation) {
+    let error = state.begin(operation, "test").err().expect("operation should be rejected");
+    assert_eq!(error.code, 0x1A);
+    assert_eq!(error.arg1, operation.as_str());
+    assert_eq!(error.arg2, running.as_str());
+  }
+
+  #[test]
+  fn nothing_touching_the_plan_starts_during_exclusive_operations() {
+    for running in EXCLUSIVE {
+      let state = OperationState::default();
+      let _guard = state.begin(running, "test").unwrap();
+      for operation in EXCLUSIVE {
+        assert_rejected(&state, operation, running);
+      }
+      assert_rejected(&state, Operation::EditPlan, running);
+    }
+  }
+
+  #[test]
+  fn compress_is_rejected_during_scan() {
+    let state = OperationState::default();
+    let _guard = state.begin(Operation::Scan, "test").unwrap();
+    assert_rejected(&state, Operation::Compress, Operation::Scan);
+  }
+
+  #[test]
+  fn compress_runs_along_with_other_operations() {
+    let state = OperationState::default();
+    let _first = state.begin(Operation::Compress, "test").unwrap();
+    let _second = state.begin(Operation::Compress, "test").unwrap();
+    assert_eq!(state.current(), "compressing");
+    assert!(state.is_compressing());
+
+    state.check(Operation::EditPlan, "test").unwrap();
+    let _scan = state.begin(Operation::Scan, "test").unwrap();
+    assert_eq!(state.current(), "scanning");
+  }
+
+  #[test]
+  fn compress_runs_during_transform_and_recover() {
+    for running in [Operation::Transform, Operation::Recover] {
+      let state = OperationState::default();
+      let _guard = state.begin(running, "test").unwrap();
+      state.begin(Operation::Compress, "test").unwrap();
+    }
+  }
+
+  #[test]
+  fn dropping_the_guard_ends_the_operation() {
+    let state = OperationState::default();
+    for operation in EXCLUSIVE.iter().copied().chain([Operation::Compress]) {
+      drop(state.begin(operation, "test").unwrap());
+      assert_eq!(state.current(), "idle");
+      assert!(!state.is_compressing());
+    }
+  }
+}
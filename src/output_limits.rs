@@ -0,0 +1,81 @@
+//! The types `OutputLimits` and `Pacer` bound how much output waits in the writer and how fast it is sent.
+
+/// throttled output is sent in slices of this many ms, so that the rate stays smooth
+const SLICE_DURATION: f64 = 250_f64;
+const MIN_SLICE_SIZE: usize = 16 * 1024;
+/// after being idle for longer, sending does not catch up with a burst, unit: ms
+const MAX_BURST_DURATION: f64 = 1000_f64;
+
+/// How output is handed to a `WritableStreamDefaultWriter`.
+#[derive(Clone, Copy, Default)]
+pub struct OutputLimits {
+  /// bytes written but not accepted by the sink yet, with `0` every write is awaited before the next one
+  pub high_water_mark: usize,
+  /// unit: byte/s, `None` is unlimited
+  pub max_bytes_per_second: Option<f64>,
+}
+
+/// Spreads writes so that on average at most `max_bytes_per_second` are sent.
+pub struct Pacer {
+  bytes_per_ms: f64,
+  /// when the current run of writes started, unit: ms
+  start: Option<f64>,
+  bytes_sent: f64,
+}
+
+impl Pacer {
+  pub fn new(max_bytes_per_second: f64) -> Self {
+    Self { bytes_per_ms: max_bytes_per_second / 1000_f64, start: None, bytes_sent: 0_f64 }
+  }
+
+  /// Largest write sent at once.
+  pub fn slice_size(&self) -> usize {
+    ((self.bytes_per_ms * SLICE_DURATION) as usize).max(MIN_SLICE_SIZE)
+  }
+
+  /// How long to wait before sending `length` bytes at `now`, unit: ms.
+  pub fn delay(&mut self, now: f64, length: usize) -> f64 {
+    let start = *self.start.get_or_insert(now);
+    let due = start + self.bytes_sent / self.bytes_per_ms;
+    let delay = if now > due + MAX_BURST_DURATION {
+      // idle for a while (paused, slow reads), start a new run
+      self.start = Some(now);
+      self.bytes_sent = 0_f64;
+      0_f64
+    } else {
+      (due - now).max(0_f64)
+    };
+    self.bytes_sent += length as f64;
+    delay
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn writes_are_spread_at_the_rate() {
+    let mut pacer = Pacer::new(100_000_f64);
+    assert_eq!(pacer.slice_size(), 25_000);
+    assert_eq!(pacer.delay(0_f64, 25_000), 0_f64);
+    assert_eq!(pacer.delay(0_f64, 25_000), 250_f64);
+    assert_eq!(pacer.delay(250_f64, 25_000), 250_f64);
+    // sending late takes nothing off later delays
+    assert_eq!(pacer.delay(900_f64, 25_000), 0_f64);
+    assert_eq!(pacer.delay(900_f64, 25_000), 100_f64);
+  }
+
+  #[test]
+  fn idle_time_does_not_allow_a_burst() {
+    let mut pacer = Pacer::new(100_000_f64);
+    pacer.delay(0_f64, 100_000);
+    assert_eq!(pacer.delay(10_000_f64, 100_000), 0_f64);
+    assert_eq!(pacer.delay(10_000_f64, 100_000), 1000_f64);
+  }
+
+  #[test]
+  fn slow_rates_still_send_reasonable_slices() {
+    assert_eq!(Pacer::new(1000_f64).slice_size(), MIN_SLICE_SIZE);
+  }
+}
@@ -0,0 +1,153 @@
+//! The type `PauseGate` lets a running job wait at a safe point until it is resumed.
+
+use std::{cell::RefCell, future::Future, pin::Pin, rc::Rc, task::{Context, Poll, Waker}};
+
+use crate::prelude::*;
+
+struct Inner {
+  paused: bool,
+  waiters: Vec<Waker>,
+}
+
+#[derive(Clone)]
+pub struct PauseGate {
+  inner: Rc<RefCell<Inner>>,
+}
+
+struct Wait {
+  inner: Rc<RefCell<Inner>>,
+  signal: Option<web_sys::AbortSignal>,
+}
+
+impl PauseGate {
+  pub fn new() -> Self {
+    Self {
+      inner: Rc::new(RefCell::new(Inner { paused: false, waiters: Vec::new() })),
+    }
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.inner.borrow().paused
+  }
+
+  pub fn pause(&self) {
+    self.inner.borrow_mut().paused = true;
+  }
+
+  pub fn resume(&self) {
+    let waiters = {
+      let mut inner = self.inner.borrow_mut();
+      inner.paused = false;
+      std::mem::take(&mut inner.waiters)
+    };
+
+    for waiter in waiters {
+      waiter.wake();
+    }
+  }
+
+  /// Resolve once resumed, or once `signal` is aborted so that the caller can stop.
+  pub async fn wait(&self, signal: Option<&web_sys::AbortSignal>) {
+    // wake the waiters when aborted, the listener is removed once resolved or dropped
+    let _on_abort = signal.map(|signal| {
+      let inner = Rc::clone(&self.inner);
+      let closure = Closure::<dyn FnMut()>::new(move || {
+        let waiters = std::mem::take(&mut inner.borrow_mut().waiters);
+        for waiter in waiters {
+          waiter.wake();
+        }
+      });
+      let _ = signal.add_event_listener_with_callback("abort", closure.as_ref().unchecked_ref());
+      AbortListener { signal: signal.clone(), closure }
+    });
+
+    Wait { inner: Rc::clone(&self.inner), signal: signal.cloned() }.await;
+  }
+}
+
+/// An "abort" listener removed when dropped, so the signal never calls a freed closure.
+struct AbortListener {
+  signal: web_sys::AbortSignal,
+  closure: Closure<dyn FnMut()>,
+}
+
+impl Drop for AbortListener {
+  fn drop(&mut self) {
+    let _ = self.signal.remove_event_listener_with_callback("abort", self.closure.as_ref().unchecked_ref());
+  }
+}
+
+impl Future for Wait {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let mut inner = self.inner.borrow_mut();
+
+    if !inner.paused || self.signal.as_ref().is_some_and(|signal| signal.aborted()) {
+      return Poll::Ready(());
+    }
+
+    if !inner.waiters.iter().any(|waiter| waiter.will_wake(cx.waker())) {
+      inner.waiters.push(cx.waker().clone());
+    }
+    Poll::Pending
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::task::Wake;
+
+  use super::*;
+
+  #[derive(Default)]
+  struct CountingWaker(AtomicUsize);
+
+  impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  #[test]
+  fn waits_until_resumed() {
+    let gate = PauseGate::new();
+    let counter = Arc::new(CountingWaker::default());
+    let waker = Waker::from(Arc::clone(&counter));
+    let mut context = Context::from_waker(&waker);
+
+    assert!(Box::pin(gate.wait(None)).as_mut().poll(&mut context).is_ready());
+
+    gate.pause();
+    let mut wait = Box::pin(gate.wait(None));
+    for _ in 0..3 {
+      assert!(wait.as_mut().poll(&mut context).is_pending());
+    }
+    assert_eq!(gate.inner.borrow().waiters.len(), 1);
+
+    gate.resume();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert!(wait.as_mut().poll(&mut context).is_ready());
+  }
+
+  #[test]
+  fn paused_again_before_polled_keeps_waiting() {
+    let gate = PauseGate::new();
+    let counter = Arc::new(CountingWaker::default());
+    let waker = Waker::from(Arc::clone(&counter));
+    let mut context = Context::from_waker(&waker);
+
+    gate.pause();
+    let mut wait = Box::pin(gate.wait(None));
+    assert!(wait.as_mut().poll(&mut context).is_pending());
+    gate.resume();
+    gate.pause();
+    assert!(wait.as_mut().poll(&mut context).is_pending());
+    assert_eq!(gate.inner.borrow().waiters.len(), 1);
+    gate.resume();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    assert!(wait.as_mut().poll(&mut context).is_ready());
+  }
+}
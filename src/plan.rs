@@ -0,0 +1,198 @@
+//! The scanned file path list viewed as an editable archive plan.
+//!
+//! Entries are identified by their original full path, while "below a directory" always refers to the
+//! archive path, so editing a directory also edits everything that will be stored under it.
+
+use crate::context::FilePath;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+/// A read-only snapshot of one scanned entry.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone)]
+pub struct PlanEntry {
+  /// full path of the dropped entry
+  pub path: String,
+  /// `"file"` or `"directory"`
+  pub kind: String,
+  /// unit: byte, `undefined` if not collected during scan
+  pub size: Option<f64>,
+  /// unit: ms since unix epoch, `undefined` if not collected during scan
+  pub mtime: Option<f64>,
+  /// path inside the zip file, without leading or trailing slash
+  pub archive_path: String,
+  /// entry name actually written after applying `LayoutOptions`, `undefined` if the entry is dropped
+  pub output_path: Option<String>,
+}
+
+#[wasm_bindgen]
+pub struct PlanTotals {
+  pub file_count: u32,
+  pub directory_count: u32,
+  /// sum of known file sizes, unit: byte
+  pub total_bytes: f64,
+  /// number of files whose size was not collected during scan
+  pub unknown_size_count: u32,
+}
+
+impl PlanEntry {
+  pub fn new(file_path: &FilePath, output_path: Option<String>) -> Self {
+    Self {
+      path: file_path.path.clone(),
+      kind: if file_path.is_dir { "directory" } else { "file" }.to_owned(),
+      size: file_path.size.map(|size| size as f64),
+      mtime: file_path.last_modified,
+      archive_path: file_path.archive_path.clone(),
+      output_path,
+    }
+  }
+}
+
+pub fn totals(file_path_list: &[FilePath]) -> PlanTotals {
+  let mut totals = PlanTotals { file_count: 0, directory_count: 0, total_bytes: 0_f64, unknown_size_count: 0 };
+  for file_path in file_path_list {
+    if file_path.is_dir {
+      totals.directory_count += 1;
+    } else {
+      totals.file_count += 1;
+      match file_path.size {
+        Some(size) => totals.total_bytes += size as f64,
+        None => totals.unknown_size_count += 1,
+      }
+    }
+  }
+  totals
+}
+
+fn is_under(archive_path: &str, directory: &str) -> bool {
+  archive_path.len() > directory.len() && archive_path.starts_with(directory) && archive_path.as_bytes()[directory.len()] == b'/'
+}
+
+fn find(file_path_list: &[FilePath], path: &str) -> Result<usize, WasmError> {
+  match file_path_list.iter().position(|file_path| file_path.path == path) {
+    Some(index) => Ok(index),
+    None => Err(WasmError::plan_entry_not_found(utils::type_name(&find), path)),
+  }
+}
+
+/// Remove an entry, a directory is removed together with everything under it, returns the number of removed entries.
+pub fn remove(file_path_list: &mut Vec<FilePath>, path: &str) -> Result<usize, WasmError> {
+  let index = find(file_path_list, path)?;
+  let removed = file_path_list.remove(index);
+
+  let mut number_of_removed = 1;
+  if removed.is_dir {
+    let length_before = file_path_list.len();
+    file_path_list.retain(|file_path| !is_under(&file_path.archive_path, &removed.archive_path));
+    number_of_removed += length_before - file_path_list.len();
+  }
+  Ok(number_of_removed)
+}
+
+/// Change the archive path of an entry, a directory takes everything under it along.
+pub fn rename(file_path_list: &mut [FilePath], path: &str, new_archive_path: &str) -> Result<(), WasmError> {
+  #[allow(non_snake_case)]
+  let LOCATION = utils::type_name(&rename);
+
+  let Some(new_archive_path) = utils::normalize_archive_path(new_archive_path) else {
+    return Err(WasmError::invalid_archive_path(LOCATION, new_archive_path));
+  };
+
+  let index = find(file_path_list, path)?;
+  let old_archive_path = file_path_list[index].archive_path.clone();
+  let is_dir = file_path_list[index].is_dir;
+  if old_archive_path == new_archive_path {
+    return Ok(());
+  }
+  if is_dir && is_under(&new_archive_path, &old_archive_path) {
+    return Err(WasmError::invalid_archive_path(LOCATION, &new_archive_path));
+  }
+
+  // the moved subtree must not land on anything that stays where it is
+  let moved = |archive_path: &str| archive_path == old_archive_path || (is_dir && is_under(archive_path, &old_archive_path));
+  let target = |archive_path: &str| format!("{}{}", new_archive_path, &archive_path[old_archive_path.len()..]);
+  for file_path in file_path_list.iter().filter(|file_path| moved(&file_path.archive_path)) {
+    let new_path = target(&file_path.archive_path);
+    if file_path_list.iter().any(|other| !moved(&other.archive_path) && other.archive_path == new_path) {
+      return Err(WasmError::duplicate_archive_path(LOCATION, &new_path));
+    }
+  }
+
+  for file_path in file_path_list.iter_mut().filter(|file_path| moved(&file_path.archive_path)) {
+    file_path.archive_path = target(&file_path.archive_path);
+  }
+  Ok(())
+}
+
+/// Move an entry under `folder` keeping its name, an empty `folder` means the root of the zip file.
+pub fn move_under(file_path_list: &mut [FilePath], path: &str, folder: &str) -> Result<(), WasmError> {
+  let index = find(file_path_list, path)?;
+  let archive_path = &file_path_list[index].archive_path;
+  let name = match archive_path.rfind('/') {
+    Some(position) => &archive_path[position + 1..],
+    None => archive_path,
+  };
+  let new_archive_path = if folder.trim_matches('/').is_empty() {
+    name.to_owned()
+  } else {
+    format!("{}/{}", folder, name)
+  };
+  rename(file_path_list, path, &new_archive_path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn plan(entries: &[(&str, bool)]) -> Vec<FilePath> {
+    entries.iter()
+      .map(|(path, is_dir)| FilePath {
+        path: format!("/{}", path),
+        is_dir: *is_dir,
+        size: None,
+        last_modified: None,
+        archive_path: path.to_string(),
+        file: None,
+      })
+      .collect()
+  }
+
+  fn archive_paths(file_path_list: &[FilePath]) -> Vec<&str> {
+    file_path_list.iter().map(|file_path| file_path.archive_path.as_str()).collect()
+  }
+
+  #[test]
+  fn renaming_a_directory_takes_its_children_along() {
+    let mut file_path_list = plan(&[("a", true), ("a/b", true), ("a/b/c.txt", false), ("ab.txt", false)]);
+    rename(&mut file_path_list, "/a", "x/y").unwrap();
+    assert_eq!(archive_paths(&file_path_list), ["x/y", "x/y/b", "x/y/b/c.txt", "ab.txt"]);
+
+    move_under(&mut file_path_list, "/a/b/c.txt", "").unwrap();
+    assert_eq!(archive_paths(&file_path_list), ["x/y", "x/y/b", "c.txt", "ab.txt"]);
+
+    assert_eq!(remove(&mut file_path_list, "/a").unwrap(), 2);
+    assert_eq!(archive_paths(&file_path_list), ["c.txt", "ab.txt"]);
+  }
+
+  #[test]
+  fn a_directory_can_not_move_under_itself() {
+    let mut file_path_list = plan(&[("a", true), ("a/b", true)]);
+    assert_eq!(rename(&mut file_path_list, "/a", "a/b/a").unwrap_err().code, 0x14);
+    assert_eq!(move_under(&mut file_path_list, "/a", "a/b").unwrap_err().code, 0x14);
+    assert_eq!(archive_paths(&file_path_list), ["a", "a/b"]);
+  }
+
+  #[test]
+  fn collisions_are_rejected_before_anything_changes() {
+    let mut file_path_list = plan(&[("a", true), ("a/x.txt", false), ("a/y.txt", false), ("b", true), ("b/y.txt", false)]);
+    let error = rename(&mut file_path_list, "/a", "b").unwrap_err();
+    assert_eq!(error.code, 0x16);
+    assert_eq!(archive_paths(&file_path_list), ["a", "a/x.txt", "a/y.txt", "b", "b/y.txt"]);
+
+    assert_eq!(move_under(&mut file_path_list, "/a/y.txt", "b").unwrap_err().code, 0x16);
+    assert_eq!(rename(&mut file_path_list, "/missing", "c").unwrap_err().code, 0x15);
+    // a free name is fine
+    rename(&mut file_path_list, "/a/x.txt", "a/z.txt").unwrap();
+    assert_eq!(archive_paths(&file_path_list), ["a", "a/z.txt", "a/y.txt", "b", "b/y.txt"]);
+  }
+}
@@ -0,0 +1,154 @@
+//! The type `ReadAhead` keeps file lookups and reads in flight while a job deflates, so round trips are not paid one by one.
+//!
+//! Lookups and reads run as tasks of their own, driven by the event loop whenever the job awaits, and also progress
+//! in the browser while a chunk is being deflated. Results are handed back in plan order.
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use futures::channel::oneshot;
+
+use crate::buffer_pool::BufferPool;
+use crate::context::FilePath;
+use crate::utils::{self, ReadResult};
+use crate::prelude::*;
+use crate::wasm_error::WasmError;
+
+/// number of files looked up ahead by default
+pub const DEFAULT_READ_AHEAD: usize = 4;
+pub const MAX_READ_AHEAD: usize = 64;
+
+/// A read started by `spawn_read`.
+pub type PendingRead = oneshot::Receiver<Result<ReadResult, WasmError>>;
+
+/// Start filling `array_buffer` from `reader` now, instead of when the result is awaited.
+pub fn spawn_read(array_buffer: js_sys::ArrayBuffer, reader: &web_sys::ReadableStreamByobReader) -> PendingRead {
+  let (sender, receiver) = oneshot::channel();
+  let reader = reader.clone();
+  wasm_bindgen_futures::spawn_local(async move {
+    let _ = sender.send(utils::byob_read(&array_buffer, &reader).await);
+  });
+  receiver
+}
+
+/// Wait for a read started by `spawn_read`.
+pub async fn wait_read(pending_read: PendingRead) -> Result<ReadResult, WasmError> {
+  match pending_read.await {
+    Ok(result) => result,
+    Err(_) => Err(WasmError::read_stream_cancelled(utils::type_name(&wait_read))),
+  }
+}
+
+/// A file with its first read started.
+pub struct OpenedFile {
+  pub file_size: u64,
+  pub reader: web_sys::ReadableStreamByobReader,
+  pub first_read: PendingRead,
+}
+
+pub struct ReadAhead {
+  /// number of files looked up ahead
+  depth: usize,
+  file_system: Option<web_sys::FileSystem>,
+  /// buffers for first reads when the job has none idle
+  buffer_pool: Rc<BufferPool>,
+  /// indices in the plan of the files not looked up yet, in plan order
+  upcoming: VecDeque<usize>,
+  lookups: VecDeque<oneshot::Receiver<Result<web_sys::File, WasmError>>>,
+  /// the next file, opened by `prefetch_next`
+  next_file: Option<oneshot::Receiver<Result<OpenedFile, WasmError>>>,
+}
+
+impl ReadAhead {
+  /// `upcoming` are the indices of every file the job reads, in plan order.
+  pub fn new(depth: usize, file_system: Option<web_sys::FileSystem>, buffer_pool: Rc<BufferPool>, upcoming: VecDeque<usize>) -> Self {
+    Self { depth, file_system, buffer_pool, upcoming, lookups: VecDeque::new(), next_file: None }
+  }
+
+  /// Keep `count` lookups in flight.
+  fn look_up_more(&mut self, file_path_list: &[FilePath], count: usize) {
+    while self.lookups.len() < count {
+      let Some(index) = self.upcoming.pop_front() else {
+        break;
+      };
+      let (sender, receiver) = oneshot::channel();
+      let file_path = file_path_list[index].clone();
+      let file_system = self.file_system.clone();
+      wasm_bindgen_futures::spawn_local(async move {
+        let _ = sender.send(file_path.open(file_system.as_ref(), utils::type_name(&Self::look_up_more)).await);
+      });
+      self.lookups.push_back(receiver);
+    }
+  }
+
+  /// Open the next file and start its first read into a buffer of `idle_buffers`, while the current file is finishing.
+  ///
+  /// Does nothing after the last file, or if read-ahead is disabled.
+  pub fn prefetch_next(&mut self, file_path_list: &[FilePath], idle_buffers: &mut Vec<js_sys::ArrayBuffer>, chunk_size: usize) {
+    if self.depth > 0 {
+      self.open_next(file_path_list, idle_buffers, chunk_size);
+    }
+  }
+
+  fn open_next(&mut self, file_path_list: &[FilePath], idle_buffers: &mut Vec<js_sys::ArrayBuffer>, chunk_size: usize) {
+    if self.next_file.is_some() {
+      return;
+    }
+    self.look_up_more(file_path_list, 1);
+    let Some(lookup) = self.lookups.pop_front() else {
+      return;
+    };
+    let array_buffer = idle_buffers.pop().unwrap_or_else(|| self.buffer_pool.take_array_buffer(chunk_size));
+
+    let (sender, receiver) = oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+      let _ = sender.send(Self::open(lookup, array_buffer).await);
+    });
+    self.next_file = Some(receiver);
+  }
+
+  async fn open(lookup: oneshot::Receiver<Result<web_sys::File, WasmError>>, array_buffer: js_sys::ArrayBuffer) -> Result<OpenedFile, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::open);
+
+    let file = match lookup.await {
+      Ok(file) => file?,
+      Err(e) => return Err(WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e))),
+    };
+    let file_size = file.size() as u64;
+
+    // cast File to Blob
+    let Ok(blob) = file.dyn_into::<web_sys::Blob>() else {
+      return Err(WasmError::dynamic_cast_error(LOCATION, "File", "Blob"));
+    };
+
+    // get BYOB reader
+    let stream = blob.stream();
+    let get_reader_option = web_sys::ReadableStreamGetReaderOptions::new();
+    get_reader_option.set_mode(web_sys::ReadableStreamReaderMode::Byob);
+    let Ok(reader) = stream.get_reader_with_options(&get_reader_option).dyn_into::<web_sys::ReadableStreamByobReader>() else {
+      return Err(WasmError::dynamic_cast_error(LOCATION, "Object", "ReadableStreamByobReader"));
+    };
+
+    let first_read = spawn_read(array_buffer, &reader);
+    Ok(OpenedFile { file_size, reader, first_read })
+  }
+
+  /// The next file in plan order, with its first read started.
+  pub async fn next(&mut self, file_path_list: &[FilePath], idle_buffers: &mut Vec<js_sys::ArrayBuffer>, chunk_size: usize) -> Result<OpenedFile, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::next);
+
+    self.open_next(file_path_list, idle_buffers, chunk_size);
+    let Some(next_file) = self.next_file.take() else {
+      return Err(WasmError::fail_to_get_file(LOCATION, "no file left in the plan"));
+    };
+    // lookups of the files after it run while it is read
+    self.look_up_more(file_path_list, self.depth);
+
+    match next_file.await {
+      Ok(opened_file) => opened_file,
+      Err(e) => Err(WasmError::fail_to_get_file(LOCATION, &format!("{:?}", e))),
+    }
+  }
+}
@@ -0,0 +1,193 @@
+//! The type `ScanOptions` controls which entries are collected by `Handles::scan_with_options`.
+
+use crate::context::FilePath;
+use crate::glob::Glob;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+/// macOS, Windows and Linux desktop junk files
+const PRESET_OS_JUNK: &[&str] = &[
+  ".DS_Store",
+  "._*",
+  ".Spotlight-V100",
+  ".Trashes",
+  ".fseventsd",
+  ".TemporaryItems",
+  "Thumbs.db",
+  "ehthumbs.db",
+  "desktop.ini",
+  "$RECYCLE.BIN",
+  ".directory",
+];
+
+/// version control system directories
+const PRESET_VCS: &[&str] = &[
+  ".git",
+  ".svn",
+  ".hg",
+  ".bzr",
+  "CVS",
+  "_darcs",
+];
+
+/// package manager directories
+const PRESET_DEPENDENCIES: &[&str] = &[
+  "node_modules",
+  "bower_components",
+];
+
+/// default number of directory reads and file lookups in flight
+const DEFAULT_CONCURRENCY: u32 = 4;
+
+#[wasm_bindgen]
+pub struct ScanOptions {
+  include: Vec<Glob>,
+  exclude: Vec<Glob>,
+  max_depth: Option<u32>,
+  use_ignore_files: bool,
+  collect_metadata: bool,
+  concurrency: u32,
+  tolerant: bool,
+  signal: Option<web_sys::AbortSignal>,
+}
+
+impl Default for ScanOptions {
+  fn default() -> Self {
+    Self {
+      include: Vec::new(),
+      exclude: Vec::new(),
+      max_depth: None,
+      use_ignore_files: false,
+      collect_metadata: false,
+      concurrency: DEFAULT_CONCURRENCY,
+      tolerant: false,
+      signal: None,
+    }
+  }
+}
+
+impl ScanOptions {
+  /// `path` is the full path of the entry without the leading slash.
+  pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+    self.exclude.iter().any(|glob| glob.is_match_entry(path, is_dir))
+  }
+
+  /// Include patterns only apply to files, directories are always traversed unless excluded.
+  pub fn is_included(&self, path: &str, is_dir: bool) -> bool {
+    is_dir || self.include.is_empty() || self.include.iter().any(|glob| glob.is_match(path))
+  }
+
+  /// With include patterns, a directory is only kept if some file below it is.
+  pub fn keeps_directory(&self, subtree: &[FilePath]) -> bool {
+    self.include.is_empty() || subtree.iter().any(|file_path| !file_path.is_dir)
+  }
+
+  /// Whether the children of a directory at `depth` should be read, top level entries have depth 0.
+  pub fn should_descend(&self, depth: u32) -> bool {
+    match self.max_depth {
+      Some(max_depth) => depth < max_depth,
+      None => true,
+    }
+  }
+
+  pub fn use_ignore_files(&self) -> bool {
+    self.use_ignore_files
+  }
+
+  pub fn collect_metadata(&self) -> bool {
+    self.collect_metadata
+  }
+
+  pub fn concurrency(&self) -> usize {
+    self.concurrency as usize
+  }
+
+  pub fn tolerant(&self) -> bool {
+    self.tolerant
+  }
+
+  pub fn signal(&self) -> Option<&web_sys::AbortSignal> {
+    self.signal.as_ref()
+  }
+
+  fn compile(pattern: &str) -> Result<Glob, WasmError> {
+    Glob::new(pattern).map_err(|reason| WasmError::invalid_glob_pattern(utils::type_name(&Self::compile), pattern, &reason))
+  }
+}
+
+#[wasm_bindgen]
+impl ScanOptions {
+  /// Create options which collect everything, like `Handles::scan` does.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Only collect files matching at least one include pattern, e.g. `**/*.log`, and the directories containing them.
+  pub fn add_include(&mut self, pattern: &str) -> Result<(), WasmError> {
+    self.include.push(Self::compile(pattern)?);
+    Ok(())
+  }
+
+  /// Skip files and directories matching the pattern, excluded directories are not descended into.
+  pub fn add_exclude(&mut self, pattern: &str) -> Result<(), WasmError> {
+    self.exclude.push(Self::compile(pattern)?);
+    Ok(())
+  }
+
+  /// Add an include pattern, or an exclude pattern if it starts with '!', e.g. `!**/.git/**`.
+  pub fn add_pattern(&mut self, pattern: &str) -> Result<(), WasmError> {
+    match pattern.strip_prefix('!') {
+      Some(pattern) => self.add_exclude(pattern),
+      None => self.add_include(pattern),
+    }
+  }
+
+  /// Exclude a built-in group of patterns.
+  ///
+  /// # Parameters
+  ///
+  /// * `preset` - one of `"os_junk"`, `"vcs"`, `"dependencies"`
+  pub fn add_preset(&mut self, preset: &str) -> Result<(), WasmError> {
+    let patterns = match preset {
+      "os_junk" => PRESET_OS_JUNK,
+      "vcs" => PRESET_VCS,
+      "dependencies" => PRESET_DEPENDENCIES,
+      _ => return Err(WasmError::unknown_scan_preset(utils::type_name(&Self::add_preset), preset)),
+    };
+    for pattern in patterns {
+      self.add_exclude(pattern)?;
+    }
+    Ok(())
+  }
+
+  /// Limit how many directory levels below the input entries are read, 0 means only the input entries themselves.
+  pub fn set_max_depth(&mut self, max_depth: u32) {
+    self.max_depth = Some(max_depth);
+  }
+
+  /// Honor `.gitignore` and `.zipignore` files found in scanned directories, disabled by default.
+  pub fn set_use_ignore_files(&mut self, use_ignore_files: bool) {
+    self.use_ignore_files = use_ignore_files;
+  }
+
+  /// Open every file during scan to collect its size and modification time, disabled by default.
+  pub fn set_collect_metadata(&mut self, collect_metadata: bool) {
+    self.collect_metadata = collect_metadata;
+  }
+
+  /// How many directories are read at once, minimal 1, 4 by default. The order of scanned entries does not depend on it.
+  pub fn set_concurrency(&mut self, concurrency: u32) {
+    self.concurrency = concurrency.max(1);
+  }
+
+  /// Record entries which can not be read in `ScanReport.problems` and keep scanning, instead of rejecting. Disabled by default.
+  pub fn set_tolerant(&mut self, tolerant: bool) {
+    self.tolerant = tolerant;
+  }
+
+  /// Stop scanning with a `WasmError` once `signal` is aborted.
+  pub fn set_signal(&mut self, signal: web_sys::AbortSignal) {
+    self.signal = Some(signal);
+  }
+}
@@ -0,0 +1,32 @@
+//! Types returned by `Handles::scan_with_options`.
+
+use crate::plan::PlanEntry;
+use crate::{prelude::*, utils};
+
+/// An entry which could not be read during a tolerant scan.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone)]
+pub struct ScanProblem {
+  /// full path of the entry
+  pub path: String,
+  /// `name` of the upstream `DomException`, like `NotReadableError`
+  pub name: String,
+  /// `message` of the upstream `DomException`
+  pub message: String,
+}
+
+#[wasm_bindgen(getter_with_clone)]
+pub struct ScanReport {
+  /// number of scanned entries
+  pub entries: u32,
+  pub plan: Vec<PlanEntry>,
+  /// always empty unless `ScanOptions.set_tolerant(true)` is used
+  pub problems: Vec<ScanProblem>,
+}
+
+impl ScanProblem {
+  pub fn new(path: &str, upstream_error: &JsValue) -> Self {
+    let (name, message) = utils::error_name_and_message(upstream_error);
+    Self { path: path.to_owned(), name, message }
+  }
+}
@@ -0,0 +1,218 @@
+//! A minimal single threaded async semaphore, used to bound how many browser requests are in flight.
+//!
+//! Waiters are served in the order they first asked, a released permit goes to the oldest waiter directly.
+
+use std::{cell::RefCell, collections::VecDeque, future::Future, pin::Pin, task::{Context, Poll, Waker}};
+
+struct Waiter {
+  id: u64,
+  waker: Waker,
+}
+
+struct Inner {
+  permits: usize,
+  next_id: u64,
+  waiters: VecDeque<Waiter>,
+  /// waiters handed a permit, not polled since
+  granted: Vec<u64>,
+}
+
+pub struct Semaphore {
+  inner: RefCell<Inner>,
+}
+
+pub struct Permit<'a> {
+  semaphore: &'a Semaphore,
+}
+
+pub struct Acquire<'a> {
+  semaphore: &'a Semaphore,
+  /// set once queued, a single slot however often it is polled
+  id: Option<u64>,
+}
+
+impl Semaphore {
+  /// `permits` is clamped to at least 1.
+  pub fn new(permits: usize) -> Self {
+    Self {
+      inner: RefCell::new(Inner { permits: permits.max(1), next_id: 0, waiters: VecDeque::new(), granted: Vec::new() }),
+    }
+  }
+
+  pub fn acquire(&self) -> Acquire<'_> {
+    Acquire { semaphore: self, id: None }
+  }
+
+  /// Hand a permit to the oldest waiter, or give it back.
+  fn release(&self) {
+    let waiter = {
+      let mut inner = self.inner.borrow_mut();
+      match inner.waiters.pop_front() {
+        Some(waiter) => {
+          inner.granted.push(waiter.id);
+          Some(waiter.waker)
+        },
+        None => {
+          inner.permits += 1;
+          None
+        },
+      }
+    };
+
+    if let Some(waker) = waiter {
+      waker.wake();
+    }
+  }
+}
+
+impl<'a> Future for Acquire<'a> {
+  type Output = Permit<'a>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    let mut inner = this.semaphore.inner.borrow_mut();
+
+    if let Some(id) = this.id {
+      if let Some(position) = inner.granted.iter().position(|granted| *granted == id) {
+        inner.granted.swap_remove(position);
+        this.id = None;
+        return Poll::Ready(Permit { semaphore: this.semaphore });
+      }
+      if let Some(waiter) = inner.waiters.iter_mut().find(|waiter| waiter.id == id) {
+        if !waiter.waker.will_wake(cx.waker()) {
+          waiter.waker = cx.waker().clone();
+        }
+      }
+      return Poll::Pending;
+    }
+
+    // nobody jumps the queue
+    if inner.permits > 0 && inner.waiters.is_empty() {
+      inner.permits -= 1;
+      return Poll::Ready(Permit { semaphore: this.semaphore });
+    }
+
+    let id = inner.next_id;
+    inner.next_id += 1;
+    inner.waiters.push_back(Waiter { id, waker: cx.waker().clone() });
+    this.id = Some(id);
+    Poll::Pending
+  }
+}
+
+impl<'a> Drop for Acquire<'a> {
+  fn drop(&mut self) {
+    let Some(id) = self.id else {
+      return;
+    };
+    let was_granted = {
+      let mut inner = self.semaphore.inner.borrow_mut();
+      inner.waiters.retain(|waiter| waiter.id != id);
+      match inner.granted.iter().position(|granted| *granted == id) {
+        Some(position) => {
+          inner.granted.swap_remove(position);
+          true
+        },
+        None => false,
+      }
+    };
+    // a permit handed to a dropped waiter goes on to the next one
+    if was_granted {
+      self.semaphore.release();
+    }
+  }
+}
+
+impl<'a> Drop for Permit<'a> {
+  fn drop(&mut self) {
+    self.semaphore.release();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::task::Wake;
+
+  use super::*;
+
+  #[derive(Default)]
+  struct CountingWaker(AtomicUsize);
+
+  impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  fn waker() -> (Arc<CountingWaker>, Waker) {
+    let counter = Arc::new(CountingWaker::default());
+    (Arc::clone(&counter), Waker::from(counter))
+  }
+
+  fn poll<'a>(acquire: &mut Acquire<'a>, waker: &Waker) -> Poll<Permit<'a>> {
+    Pin::new(acquire).poll(&mut Context::from_waker(waker))
+  }
+
+  #[test]
+  fn repeated_polls_keep_one_slot_and_waiters_are_served_in_order() {
+    let semaphore = Semaphore::new(1);
+    let (_, idle) = waker();
+    let (first_count, first_waker) = waker();
+    let (second_count, second_waker) = waker();
+
+    let mut acquire = semaphore.acquire();
+    let Poll::Ready(permit) = poll(&mut acquire, &idle) else {
+      panic!("a free permit is taken at once");
+    };
+
+    let mut first = semaphore.acquire();
+    let mut second = semaphore.acquire();
+    for _ in 0..3 {
+      assert!(poll(&mut first, &first_waker).is_pending());
+      assert!(poll(&mut second, &second_waker).is_pending());
+    }
+    assert_eq!(semaphore.inner.borrow().waiters.len(), 2);
+
+    drop(permit);
+    assert_eq!(first_count.0.load(Ordering::SeqCst), 1);
+    assert_eq!(second_count.0.load(Ordering::SeqCst), 0);
+    // a newcomer does not take the permit handed to the first waiter
+    assert!(poll(&mut semaphore.acquire(), &idle).is_pending());
+    assert!(poll(&mut second, &second_waker).is_pending());
+    let Poll::Ready(permit) = poll(&mut first, &first_waker) else {
+      panic!("the first waiter is served first");
+    };
+
+    drop(permit);
+    assert_eq!(second_count.0.load(Ordering::SeqCst), 1);
+    assert!(poll(&mut second, &second_waker).is_ready());
+  }
+
+  #[test]
+  fn a_dropped_waiter_passes_its_permit_on() {
+    let semaphore = Semaphore::new(1);
+    let (_, idle) = waker();
+    let (second_count, second_waker) = waker();
+
+    let mut acquire = semaphore.acquire();
+    let Poll::Ready(permit) = poll(&mut acquire, &idle) else {
+      panic!("a free permit is taken at once");
+    };
+    let mut first = semaphore.acquire();
+    let mut second = semaphore.acquire();
+    assert!(poll(&mut first, &idle).is_pending());
+    assert!(poll(&mut second, &second_waker).is_pending());
+
+    drop(permit);
+    drop(first);
+    assert_eq!(second_count.0.load(Ordering::SeqCst), 1);
+    let Poll::Ready(permit) = poll(&mut second, &second_waker) else {
+      panic!("the permit of the dropped waiter is handed on");
+    };
+    drop(permit);
+    drop(second);
+    assert_eq!(semaphore.inner.borrow().permits, 1);
+  }
+}
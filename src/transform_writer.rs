@@ -1,43 +1,130 @@
 //! NOT IMPLEMENTED
 
+use std::collections::VecDeque;
 use std::io::Write;
 
+use crate::buffer_pool::BufferPool;
+use crate::output_limits::{OutputLimits, Pacer};
 use crate::{prelude::*, utils};
 use crate::wasm_error::WasmError;
 
 pub struct TransformWriter<'a> {
   writer: &'a web_sys::WritableStreamDefaultWriter,
+  /// output is copied into its array buffers, each given back once its write settled
+  buffer_pool: &'a BufferPool,
   threshold: usize,
   transform_buffer: Vec<u8>,
   result_buffer: Vec<u8>,
   bypass: bool,
+  high_water_mark: usize,
+  pacer: Option<Pacer>,
+  /// writes not settled yet, with their length and the buffer of a full piece
+  pending_writes: VecDeque<(js_sys::Promise, usize, Option<js_sys::ArrayBuffer>)>,
+  queued_bytes: usize,
 }
 
 impl<'a> TransformWriter<'a> {
   pub fn new(
     writer: &'a web_sys::WritableStreamDefaultWriter,
+    buffer_pool: &'a BufferPool,
     threshold: usize,
     transform_buffer_size: usize,
-    result_buffer_size: usize,
-    bypass: bool
+    result_buffer: Vec<u8>,
+    bypass: bool,
+    limits: OutputLimits,
   ) -> Self {
     Self {
       writer,
+      buffer_pool,
       threshold,
       transform_buffer: Vec::with_capacity(transform_buffer_size),
-      result_buffer: Vec::with_capacity(result_buffer_size),
+      result_buffer,
       bypass,
+      high_water_mark: limits.high_water_mark,
+      pacer: limits.max_bytes_per_second.map(Pacer::new),
+      pending_writes: VecDeque::new(),
+      queued_bytes: 0,
     }
   }
 
+  /// Give back the result buffer so that it can be reused, anything not dumped yet is dropped.
+  pub fn into_result_buffer(self) -> Vec<u8> {
+    self.result_buffer
+  }
+
+  /// Send the result buffer once it reaches the threshold, or whatever it holds if `use_threshold` is false.
+  ///
+  /// Output is sent in pieces of the threshold (smaller with a bandwidth cap), each copied into an array buffer of its
+  /// own length, so the queue never holds more than `queued_bytes`. With the threshold, a partial piece stays buffered.
+  ///
+  /// Writes are not awaited one by one: up to the high-water mark stays queued in the writer while compression goes on.
+  /// Without the threshold, this also waits until the sink has accepted everything.
   pub async fn dump(&mut self, use_threshold: bool) -> Result<(), WasmError> {
-    if (use_threshold && self.result_buffer.len() >= self.threshold) || (!use_threshold && self.result_buffer.len() > 0) {
-      let array_buffer_write = js_sys::ArrayBuffer::new(self.result_buffer.len() as u32);
-      let view_write = js_sys::Uint8Array::new(&array_buffer_write);
-      view_write.copy_from(&self.result_buffer);
-      utils::await_promise(self.writer.ready()).await.unwrap();
-      utils::write(self.writer, &view_write).await?;
-      self.result_buffer.clear();
+    if (use_threshold && self.result_buffer.len() >= self.threshold) || (!use_threshold && !self.result_buffer.is_empty()) {
+      let piece_size = self.pacer.as_ref().map_or(self.threshold, |pacer| pacer.slice_size().min(self.threshold)).max(1);
+      let length = self.result_buffer.len();
+      let mut offset = 0;
+      while offset < length && (!use_threshold || length - offset >= piece_size) {
+        let end = (offset + piece_size).min(length);
+        self.wait_for_room(end - offset).await?;
+        if let Some(pacer) = self.pacer.as_mut() {
+          let delay = pacer.delay(js_sys::Date::now(), end - offset);
+          if delay > 0_f64 {
+            utils::sleep(delay).await;
+          }
+        }
+        // full pieces all have the same size and go back to the pool, a trailing one is copied on its own
+        let (chunk, array_buffer) = if end - offset == piece_size {
+          let array_buffer = self.buffer_pool.take_array_buffer(piece_size);
+          let chunk = js_sys::Uint8Array::new(&array_buffer);
+          chunk.copy_from(&self.result_buffer[offset..end]);
+          (chunk, Some(array_buffer))
+        } else {
+          (js_sys::Uint8Array::from(&self.result_buffer[offset..end]), None)
+        };
+        self.pending_writes.push_back((self.writer.write_with_chunk(&chunk), end - offset, array_buffer));
+        self.queued_bytes += end - offset;
+        offset = end;
+      }
+      self.result_buffer.drain(..offset);
+    }
+    if !use_threshold {
+      while !self.pending_writes.is_empty() {
+        self.settle_oldest_write().await?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Wait until `length` more bytes fit below the high-water mark, and until the writer itself asks for more.
+  async fn wait_for_room(&mut self, length: usize) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::wait_for_room);
+
+    while !self.pending_writes.is_empty() && self.queued_bytes + length > self.high_water_mark {
+      self.settle_oldest_write().await?;
+    }
+    // `desiredSize` is null once the stream is errored, the write then rejects
+    if matches!(self.writer.desired_size(), Ok(Some(desired_size)) if desired_size <= 0_f64) {
+      if let Err(e) = utils::await_promise(self.writer.ready()).await {
+        return Err(WasmError::fail_to_write(LOCATION, &format!("{:?}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  async fn settle_oldest_write(&mut self) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::settle_oldest_write);
+
+    if let Some((promise, length, array_buffer)) = self.pending_writes.pop_front() {
+      self.queued_bytes -= length;
+      if let Err(e) = utils::await_promise(promise).await {
+        return Err(WasmError::fail_to_write(LOCATION, &format!("{:?}", e)));
+      }
+      if let Some(array_buffer) = array_buffer {
+        self.buffer_pool.give_array_buffer(array_buffer);
+      }
     }
     Ok(())
   }
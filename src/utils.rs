@@ -22,6 +22,86 @@ pub async fn write(writer: &web_sys::WritableStreamDefaultWriter, view: &js_sys:
   Ok(())
 }
 
+/// Return `WasmError::aborted` if `signal` has been aborted.
+pub fn check_signal(signal: Option<&web_sys::AbortSignal>, location: &str) -> Result<(), WasmError> {
+  match signal {
+    Some(signal) if signal.aborted() => {
+      let reason = signal.reason();
+      let reason = match reason.as_string() {
+        Some(reason) => reason,
+        None => {
+          let (name, message) = error_name_and_message(&reason);
+          format!("{}: {}", name, message)
+        },
+      };
+      Err(WasmError::aborted(location, &reason))
+    },
+    _ => Ok(()),
+  }
+}
+
+/// Like `check_signal`, but also abort `writer` with the reason of `signal`, so that the half written output is discarded.
+pub async fn check_signal_and_abort_writer(signal: Option<&web_sys::AbortSignal>, writer: &web_sys::WritableStreamDefaultWriter, location: &str) -> Result<(), WasmError> {
+  if let Err(e) = check_signal(signal, location) {
+    // the writer may already be errored, there is nothing more to do in that case
+    let _ = await_promise(writer.abort_with_reason(&signal.unwrap().reason())).await;
+    return Err(e);
+  }
+  Ok(())
+}
+
+/// Read the whole content of a blob as text, the error is the upstream rejection reason.
+pub async fn read_text(blob: &web_sys::Blob) -> Result<String, JsValue> {
+  let text = await_promise(blob.text()).await?;
+  text.as_string().ok_or(text)
+}
+
+/// Extract `name` and `message` from a `DomException` or any other `Error` like object.
+pub fn error_name_and_message(error: &JsValue) -> (String, String) {
+  if let Some(dom_exception) = error.dyn_ref::<web_sys::DomException>() {
+    return (dom_exception.name(), dom_exception.message());
+  }
+  if let Some(error) = error.dyn_ref::<js_sys::Error>() {
+    return (String::from(error.name()), String::from(error.message()));
+  }
+  ("Error".to_owned(), format!("{:?}", error))
+}
+
+/// Resolve after `ms`, on the main thread as well as in a worker.
+pub async fn sleep(ms: f64) {
+  let promise = js_sys::Promise::new(&mut |resolve, _| {
+    let set_timeout = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))
+      .ok()
+      .and_then(|set_timeout| set_timeout.dyn_into::<js_sys::Function>().ok());
+    match set_timeout {
+      Some(set_timeout) => {
+        let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(ms));
+      },
+      None => {
+        let _ = resolve.call0(&JsValue::NULL);
+      },
+    }
+  });
+  let _ = await_promise(promise).await;
+}
+
+/// Normalize a user supplied path inside the zip file, `None` if it is empty or escapes the root.
+pub fn normalize_archive_path(archive_path: &str) -> Option<String> {
+  let archive_path = archive_path.replace('\\', "/");
+  let mut components = Vec::new();
+  for component in archive_path.split('/') {
+    match component {
+      "" | "." => continue,
+      ".." => return None,
+      component => components.push(component),
+    }
+  }
+  if components.is_empty() {
+    return None;
+  }
+  Some(components.join("/"))
+}
+
 #[wasm_bindgen]
 extern "C" {
   type ReadResultJS;
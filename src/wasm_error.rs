@@ -190,4 +190,134 @@ impl WasmError {
       "",
     )
   }
+
+  pub fn invalid_glob_pattern(location: &str, pattern: &str, reason: &str) -> Self {
+    Self::new(
+      0x00000011_u32,
+      location,
+      pattern,
+      reason,
+      "",
+    )
+  }
+
+  pub fn unknown_scan_preset(location: &str, preset: &str) -> Self {
+    Self::new(
+      0x00000012_u32,
+      location,
+      preset,
+      "",
+      "",
+    )
+  }
+
+  pub fn invalid_scan_filter_result(location: &str, path: &str, result: &str) -> Self {
+    Self::new(
+      0x00000013_u32,
+      location,
+      path,
+      result,
+      "",
+    )
+  }
+
+  pub fn invalid_archive_path(location: &str, archive_path: &str) -> Self {
+    Self::new(
+      0x00000014_u32,
+      location,
+      archive_path,
+      "",
+      "",
+    )
+  }
+
+  pub fn plan_entry_not_found(location: &str, path: &str) -> Self {
+    Self::new(
+      0x00000015_u32,
+      location,
+      path,
+      "",
+      "",
+    )
+  }
+
+  pub fn duplicate_archive_path(location: &str, archive_path: &str) -> Self {
+    Self::new(
+      0x00000016_u32,
+      location,
+      archive_path,
+      "",
+      "",
+    )
+  }
+
+  pub fn fail_to_read_directory(location: &str, path: &str, upstream_error: &str) -> Self {
+    Self::new(
+      0x00000017_u32,
+      location,
+      path,
+      upstream_error,
+      "",
+    )
+  }
+
+  pub fn aborted(location: &str, reason: &str) -> Self {
+    Self::new(
+      0x00000018_u32,
+      location,
+      reason,
+      "",
+      "",
+    )
+  }
+
+  pub fn job_already_started(location: &str) -> Self {
+    Self::new(
+      0x00000019_u32,
+      location,
+      "",
+      "",
+      "",
+    )
+  }
+
+  pub fn operation_in_progress(location: &str, requested: &str, running: &str) -> Self {
+    Self::new(
+      0x0000001A_u32,
+      location,
+      requested,
+      running,
+      "",
+    )
+  }
+
+  pub fn unknown_option(location: &str, key: &str) -> Self {
+    Self::new(
+      0x0000001B_u32,
+      location,
+      key,
+      "",
+      "",
+    )
+  }
+
+  pub fn invalid_option(location: &str, key: &str, value: &str, expected: &str) -> Self {
+    Self::new(
+      0x0000001C_u32,
+      location,
+      key,
+      value,
+      expected,
+    )
+  }
+
+  pub fn worker_failure(location: &str, upstream_error: &str) -> Self {
+    Self::new(
+      0x0000001D_u32,
+      location,
+      upstream_error,
+      "",
+      "",
+    )
+  }
 }
@@ -0,0 +1,377 @@
+//! The type `WorkerPool` compresses whole files on Web Workers, only built with the `worker-pool` feature.
+//!
+//! Every worker runs its own instance of this module and calls `compress_in_worker`, see `js/compress_worker.js`.
+//! Files are sent as `Blob`s and the compressed data comes back as a transferred `ArrayBuffer`, so nothing is shared
+//! between the instances and no cross-origin isolation is needed.
+//!
+//! `PooledEntries` looks up the files of a job ahead of the compress loop and sends them to the pool as blocks,
+//! a file larger than a block is split like pigz does (see `block_deflate`), so even a single huge file keeps every
+//! worker busy. Blocks are handed back strictly in plan order, the job writes them as they come.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use crc32fast::Hasher;
+use futures::channel::oneshot;
+
+use crate::block_deflate::{self, DICTIONARY_SIZE};
+use crate::buffer_pool::MemoryBudget;
+use crate::context::FilePath;
+use crate::{prelude::*, utils};
+use crate::wasm_error::WasmError;
+
+/// Result of `compress_in_worker`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct CompressedData {
+  /// raw deflate data, ending with a sync flush unless it is the last block
+  pub data: js_sys::Uint8Array,
+  /// of the block only, without the dictionary
+  pub crc32: u32,
+  /// unit: byte
+  pub uncompressed_size: f64,
+}
+
+/// Compress one block of a file, called by the worker script, never on the thread running the job.
+///
+/// * `slice` - the dictionary followed by the block
+/// * `dictionary_length` - unit: byte, `0` for the first block
+/// * `last` - whether the block ends the file
+#[wasm_bindgen]
+pub async fn compress_in_worker(slice: web_sys::Blob, compression_level: u32, dictionary_length: u32, last: bool) -> Result<CompressedData, WasmError> {
+  #[allow(non_snake_case)]
+  let LOCATION = utils::type_name(&compress_in_worker);
+
+  if compression_level > 9 {
+    return Err(WasmError::invalid_compression_level(LOCATION, &format!("{}", compression_level)));
+  }
+
+  let array_buffer = match utils::await_promise(slice.array_buffer()).await {
+    Ok(array_buffer) => array_buffer,
+    Err(e) => return Err(WasmError::fail_to_read(LOCATION, &format!("{:?}", e))),
+  };
+  let content = js_sys::Uint8Array::new(&array_buffer).to_vec();
+  let (dictionary, block) = content.split_at((dictionary_length as usize).min(content.len()));
+
+  let mut crc32_hasher = Hasher::new();
+  crc32_hasher.update(block);
+
+  let data = match block_deflate::compress_block(dictionary, block, compression_level, last) {
+    Ok(data) => data,
+    Err(e) => return Err(WasmError::fail_to_compress(LOCATION, &format!("{:?}", e))),
+  };
+
+  Ok(CompressedData {
+    data: js_sys::Uint8Array::from(&data[..]),
+    crc32: crc32_hasher.finalize(),
+    uncompressed_size: block.len() as f64,
+  })
+}
+
+/// A compressed block back on the thread running the job.
+pub struct CompressedBlock {
+  pub data: Vec<u8>,
+  pub crc32: u32,
+  pub uncompressed_size: u64,
+}
+
+type Reply = Result<CompressedBlock, WasmError>;
+
+struct Task {
+  id: u32,
+  slice: web_sys::Blob,
+  compression_level: u32,
+  dictionary_length: u32,
+  last: bool,
+}
+
+struct Inner {
+  workers: Vec<web_sys::Worker>,
+  /// id of the task each worker is running
+  running: Vec<Option<u32>>,
+  /// workers taken out of rotation by an error event
+  failed: Vec<bool>,
+  queue: VecDeque<Task>,
+  pending: HashMap<u32, oneshot::Sender<Reply>>,
+  next_id: u32,
+}
+
+pub struct WorkerPool {
+  inner: Rc<RefCell<Inner>>,
+  _on_message: Vec<Closure<dyn FnMut(web_sys::MessageEvent)>>,
+  _on_error: Vec<Closure<dyn FnMut(web_sys::Event)>>,
+}
+
+impl WorkerPool {
+  /// Every worker must load `js/compress_worker.js` or an equivalent script.
+  pub fn new(workers: Vec<web_sys::Worker>) -> Self {
+    let inner = Rc::new(RefCell::new(Inner {
+      running: vec![None; workers.len()],
+      failed: vec![false; workers.len()],
+      workers: workers.clone(),
+      queue: VecDeque::new(),
+      pending: HashMap::new(),
+      next_id: 0,
+    }));
+
+    let mut on_message = Vec::with_capacity(workers.len());
+    let mut on_error = Vec::with_capacity(workers.len());
+    for (index, worker) in workers.iter().enumerate() {
+      let inner_message = Rc::clone(&inner);
+      let message_closure = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |event: web_sys::MessageEvent| {
+        Self::finish(&inner_message, index, Self::parse_reply(&event.data()));
+      });
+      worker.set_onmessage(Some(message_closure.as_ref().unchecked_ref()));
+      on_message.push(message_closure);
+
+      // e.g. the script fails to load, the running task fails instead of waiting forever
+      let inner_error = Rc::clone(&inner);
+      let error_closure = Closure::<dyn FnMut(web_sys::Event)>::new(move |event: web_sys::Event| {
+        let location = utils::type_name(&Self::new);
+        Self::fail(&inner_error, index, WasmError::worker_failure(location, &format!("{:?}", event)));
+      });
+      worker.set_onerror(Some(error_closure.as_ref().unchecked_ref()));
+      on_error.push(error_closure);
+    }
+
+    Self { inner, _on_message: on_message, _on_error: on_error }
+  }
+
+  pub fn size(&self) -> usize {
+    self.inner.borrow().workers.len()
+  }
+
+  /// Queue a block, the receiver gets the result once a worker is done with it, see `compress_in_worker`.
+  fn submit(&self, slice: web_sys::Blob, compression_level: u32, dictionary_length: u32, last: bool) -> oneshot::Receiver<Reply> {
+    let (sender, receiver) = oneshot::channel();
+    {
+      let mut inner = self.inner.borrow_mut();
+      let id = inner.next_id;
+      inner.next_id = inner.next_id.wrapping_add(1);
+      inner.pending.insert(id, sender);
+      inner.queue.push_back(Task { id, slice, compression_level, dictionary_length, last });
+    }
+    Self::dispatch(&self.inner);
+    receiver
+  }
+
+  /// Hand queued tasks to idle workers, tasks nobody waits for anymore are dropped.
+  ///
+  /// Once every worker failed, every task fails, queued or submitted later.
+  fn dispatch(inner: &Rc<RefCell<Inner>>) {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::dispatch);
+
+    let mut inner = inner.borrow_mut();
+    if inner.failed.iter().all(|failed| *failed) {
+      inner.queue.clear();
+      for (_, sender) in inner.pending.drain() {
+        let _ = sender.send(Err(WasmError::worker_failure(LOCATION, "every worker of the pool failed")));
+      }
+      return;
+    }
+    while let Some(index) = (0..inner.workers.len()).find(|index| inner.running[*index].is_none() && !inner.failed[*index]) {
+      let Some(task) = inner.queue.pop_front() else {
+        break;
+      };
+      let waited_for = inner.pending.get(&task.id).is_some_and(|sender| !sender.is_canceled());
+      if !waited_for {
+        inner.pending.remove(&task.id);
+        continue;
+      }
+
+      let message = js_sys::Object::new();
+      let _ = js_sys::Reflect::set(&message, &JsValue::from_str("id"), &JsValue::from(task.id));
+      let _ = js_sys::Reflect::set(&message, &JsValue::from_str("slice"), &task.slice);
+      let _ = js_sys::Reflect::set(&message, &JsValue::from_str("level"), &JsValue::from(task.compression_level));
+      let _ = js_sys::Reflect::set(&message, &JsValue::from_str("dictionaryLength"), &JsValue::from(task.dictionary_length));
+      let _ = js_sys::Reflect::set(&message, &JsValue::from_str("last"), &JsValue::from(task.last));
+      match inner.workers[index].post_message(&message) {
+        Ok(()) => inner.running[index] = Some(task.id),
+        Err(e) => {
+          if let Some(sender) = inner.pending.remove(&task.id) {
+            let _ = sender.send(Err(WasmError::worker_failure(LOCATION, &format!("{:?}", e))));
+          }
+        },
+      }
+    }
+  }
+
+  /// Deliver the reply of worker `index`, then give it the next task.
+  fn finish(inner: &Rc<RefCell<Inner>>, index: usize, reply: Reply) {
+    {
+      let mut inner = inner.borrow_mut();
+      if let Some(id) = inner.running[index].take() {
+        if let Some(sender) = inner.pending.remove(&id) {
+          let _ = sender.send(reply);
+        }
+      }
+    }
+    Self::dispatch(inner);
+  }
+
+  /// Take worker `index` out of rotation after an error event, the task it was running fails with `error`.
+  fn fail(inner: &Rc<RefCell<Inner>>, index: usize, error: WasmError) {
+    {
+      let mut inner = inner.borrow_mut();
+      inner.failed[index] = true;
+      inner.workers[index].terminate();
+    }
+    Self::finish(inner, index, Err(error));
+  }
+
+  /// `{ id, data, crc32, uncompressedSize }` or `{ id, error }`, as posted by `js/compress_worker.js`.
+  fn parse_reply(message: &JsValue) -> Reply {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::parse_reply);
+
+    let get = |key: &str| js_sys::Reflect::get(message, &JsValue::from_str(key)).unwrap_or(JsValue::UNDEFINED);
+
+    let error = get("error");
+    if !error.is_undefined() {
+      // a `WasmError` thrown by `compress_in_worker` arrives as a plain object
+      let code = js_sys::Reflect::get(&error, &JsValue::from_str("code")).ok().and_then(|code| code.as_f64());
+      let upstream_error = match code {
+        Some(code) => format!("WasmError 0x{:X} {:?}", code as u32, js_sys::Reflect::get(&error, &JsValue::from_str("arg1")).unwrap_or(JsValue::UNDEFINED)),
+        None => format!("{:?}", error),
+      };
+      return Err(WasmError::worker_failure(LOCATION, &upstream_error));
+    }
+
+    let Ok(data) = get("data").dyn_into::<js_sys::Uint8Array>() else {
+      return Err(WasmError::dynamic_cast_error(LOCATION, "JsValue", "Uint8Array"));
+    };
+    let (Some(crc32), Some(uncompressed_size)) = (get("crc32").as_f64(), get("uncompressedSize").as_f64()) else {
+      return Err(WasmError::worker_failure(LOCATION, &format!("{:?}", message)));
+    };
+    Ok(CompressedBlock { data: data.to_vec(), crc32: crc32 as u32, uncompressed_size: uncompressed_size as u64 })
+  }
+}
+
+impl Drop for WorkerPool {
+  fn drop(&mut self) {
+    for worker in self.inner.borrow().workers.iter() {
+      worker.set_onmessage(None);
+      worker.set_onerror(None);
+      worker.terminate();
+    }
+  }
+}
+
+/// Blocks of one file, submitted a few at a time and handed back in order.
+pub struct CompressedBlocks {
+  worker_pool: Rc<WorkerPool>,
+  file: web_sys::File,
+  compression_level: u32,
+  /// unit: byte
+  block_size: u64,
+  /// number of blocks in flight
+  depth: usize,
+  /// unit: byte, start of the next block to submit
+  next_offset: u64,
+  /// whether the last block has been submitted, an empty file still has one
+  submitted_all: bool,
+  submitted: VecDeque<oneshot::Receiver<Reply>>,
+}
+
+impl CompressedBlocks {
+  /// unit: byte
+  pub fn file_size(&self) -> u64 {
+    self.file.size() as u64
+  }
+
+  /// Keep `depth` blocks in flight.
+  fn submit_more(&mut self, depth: usize) -> Result<(), WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::submit_more);
+
+    let file_size = self.file_size();
+    while self.submitted.len() < depth && !self.submitted_all {
+      let start = self.next_offset;
+      let end = (start + self.block_size).min(file_size);
+      let dictionary_start = start.saturating_sub(DICTIONARY_SIZE as u64);
+      let slice = match self.file.slice_with_f64_and_f64(dictionary_start as f64, end as f64) {
+        Ok(slice) => slice,
+        Err(e) => return Err(WasmError::fail_to_read(LOCATION, &format!("{:?}", e))),
+      };
+      let last = end == file_size;
+      self.submitted.push_back(self.worker_pool.submit(slice, self.compression_level, (start - dictionary_start) as u32, last));
+      self.next_offset = end;
+      self.submitted_all = last;
+    }
+    Ok(())
+  }
+
+  /// The next compressed block, `None` after the last one.
+  pub async fn next(&mut self) -> Result<Option<CompressedBlock>, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::next);
+
+    self.submit_more(self.depth)?;
+    let Some(receiver) = self.submitted.pop_front() else {
+      return Ok(None);
+    };
+    match receiver.await {
+      Ok(reply) => Ok(Some(reply?)),
+      Err(e) => Err(WasmError::worker_failure(LOCATION, &format!("{:?}", e))),
+    }
+  }
+}
+
+/// Files of one job on their way through the pool.
+///
+/// At most `depth` files are looked up ahead with their first block submitted, and at most `depth` blocks of the file
+/// being written are in flight, so finished blocks waiting for their turn never hold much more than
+/// `2 * depth * block_size` bytes, which `MemoryBudget::pool_blocks` keeps within the budget of the job.
+pub struct PooledEntries {
+  worker_pool: Rc<WorkerPool>,
+  compression_level: u32,
+  block_size: u64,
+  depth: usize,
+  /// indices in the plan of the files not looked up yet, in plan order
+  upcoming: VecDeque<usize>,
+  prepared: VecDeque<Result<CompressedBlocks, WasmError>>,
+}
+
+impl PooledEntries {
+  /// `upcoming` are the indices of every file the job writes, in plan order.
+  pub fn new(worker_pool: Rc<WorkerPool>, upcoming: VecDeque<usize>, compression_level: u32, memory_budget: &MemoryBudget) -> Self {
+    let (block_size, depth) = memory_budget.pool_blocks(worker_pool.size());
+    Self { worker_pool, compression_level, block_size: block_size as u64, depth, upcoming, prepared: VecDeque::new() }
+  }
+
+  /// The blocks of the next file in plan order, files are looked up ahead to keep every worker busy.
+  pub async fn next(&mut self, file_path_list: &[FilePath], file_system: Option<&web_sys::FileSystem>) -> Result<CompressedBlocks, WasmError> {
+    #[allow(non_snake_case)]
+    let LOCATION = utils::type_name(&Self::next);
+
+    while self.prepared.len() < self.depth {
+      let Some(index) = self.upcoming.pop_front() else {
+        break;
+      };
+      let prepared = match file_path_list[index].open(file_system, LOCATION).await {
+        Ok(file) => {
+          let mut blocks = CompressedBlocks {
+            worker_pool: Rc::clone(&self.worker_pool),
+            file,
+            compression_level: self.compression_level,
+            block_size: self.block_size,
+            depth: self.depth,
+            next_offset: 0,
+            submitted_all: false,
+            submitted: VecDeque::new(),
+          };
+          blocks.submit_more(1).map(|_| blocks)
+        },
+        // reported when its turn comes
+        Err(e) => Err(e),
+      };
+      self.prepared.push_back(prepared);
+    }
+
+    match self.prepared.pop_front() {
+      Some(prepared) => prepared,
+      None => Err(WasmError::worker_failure(LOCATION, "no file left in the plan")),
+    }
+  }
+}
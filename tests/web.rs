@@ -11,3 +11,83 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+/// The JS glue rejects `&mut self` methods while an async method holds the object, settings take `&self`.
+#[wasm_bindgen_test]
+async fn settings_change_while_compressing() {
+    use wasm_zip_stream::initialize_context;
+    use web_sys::js_sys;
+    use web_sys::wasm_bindgen::{JsCast, JsValue};
+
+    let create_writer = js_sys::Function::new_no_args("return new WritableStream().getWriter();");
+    let handles = initialize_context(create_writer);
+    let files = js_sys::Function::new_no_args("return [{ path: 'a.txt', file: new File(['hello'], 'a.txt') }];")
+        .call0(&JsValue::NULL)
+        .unwrap();
+    handles.scan_files(files.unchecked_into()).unwrap();
+
+    let compress = handles.compress("out".to_owned(), 6, None);
+    futures::pin_mut!(compress);
+    assert!(futures::poll!(compress.as_mut()).is_pending());
+    assert_eq!(handles.state(), "compressing");
+
+    let eta_calls = js_sys::Array::new();
+    let eta = js_sys::Function::new_with_args("calls", "return () => calls.push(1);").call1(&JsValue::NULL, &eta_calls).unwrap();
+    handles.register_eta(eta.unchecked_into());
+    handles.register_compress_progress(js_sys::Function::new_no_args(""));
+
+    let summary = compress.await.map_err(|e| e.code).unwrap();
+    assert_eq!(summary.entries_written, 1);
+    // the running job keeps the callbacks it started with
+    assert_eq!(eta_calls.length(), 0);
+}
+
+/// Entries deflated by `CompressionStream` are plain raw deflate streams, reported without a level.
+#[wasm_bindgen_test]
+async fn native_codec_output_inflates_to_the_input() {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+    use wasm_zip_stream::initialize_context;
+    use web_sys::js_sys;
+    use web_sys::wasm_bindgen::{JsCast, JsValue};
+
+    let chunks = js_sys::Array::new();
+    let create_writer = js_sys::Function::new_with_args(
+        "chunks",
+        "return () => new WritableStream({ write(chunk) { chunks.push(chunk.slice()); } }).getWriter();",
+    )
+    .call1(&JsValue::NULL, &chunks)
+    .unwrap();
+    let handles = initialize_context(create_writer.unchecked_into());
+    let input: Vec<u8> = (0..100_000_u32).flat_map(|index| format!("{} {}\n", index, index % 17).into_bytes()).collect();
+    let files = js_sys::Function::new_with_args("data", "return [{ path: 'a.txt', file: new File([data], 'a.txt') }];")
+        .call1(&JsValue::NULL, &js_sys::Uint8Array::from(&input[..]))
+        .unwrap();
+    handles.scan_files(files.unchecked_into()).unwrap();
+
+    let options = js_sys::Function::new_no_args("return { fileName: 'out', level: 6, codec: 'native' };")
+        .call0(&JsValue::NULL)
+        .unwrap();
+    let summary = handles.compress_with_options(options.unchecked_into()).await.map_err(|e| e.code).unwrap();
+    let entries = summary.entries();
+    if entries[0].codec != "native" {
+        // `CompressionStream("deflate-raw")` is missing in this browser
+        return;
+    }
+    assert_eq!(entries[0].level, 0);
+
+    let mut output = Vec::new();
+    for chunk in chunks.iter() {
+        output.extend(chunk.unchecked_into::<js_sys::Uint8Array>().to_vec());
+    }
+    // the data follows the local file header, its name and its extra field
+    let name_length = u16::from_le_bytes([output[26], output[27]]) as usize;
+    let extra_length = u16::from_le_bytes([output[28], output[29]]) as usize;
+    let start = 30 + name_length + extra_length;
+    let data = &output[start..start + entries[0].compressed_size as usize];
+
+    let mut inflated = Vec::new();
+    DeflateDecoder::new(data).read_to_end(&mut inflated).unwrap();
+    assert_eq!(inflated, input);
+}